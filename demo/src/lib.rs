@@ -31,7 +31,8 @@ pub async fn main_entry() {
 
     let offscreen = canvas.transfer_control_to_offscreen().unwrap_throw();
 
-    let (mut worker, mut response) = shogo::EngineMain::new("./worker.js", offscreen).await;
+    let (mut worker, mut response) =
+        shogo::EngineMain::new_or_throw("./worker.js", offscreen).await;
 
     let _handler = worker.register_event(&canvas, "mousemove", |e| {
         let [x, y] = convert_coord(e.elem, e.event);
@@ -79,8 +80,8 @@ pub async fn worker_entry() {
     let radius = 4.0;
     let game_dim = [canvas.width() as f32, canvas.height() as f32];
 
-    'outer: loop {
-        for e in frame_timer.next().await {
+    'outer: while let Some(events) = frame_timer.next().await {
+        for e in events {
             match e {
                 MEvent::CanvasMouseMove { x, y } => mouse_pos = [*x, *y],
                 MEvent::ButtonClick => {
@@ -7,8 +7,7 @@ use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 
-use gloo::utils::format::JsValueSerdeExt;
-
+pub mod rng;
 pub mod simple2d;
 
 pub mod utils {
@@ -40,10 +39,150 @@ pub mod utils {
     }
 
     ///
-    /// Get a webgl2 context for an offscreen canvas element.
+    /// Make an element, e.g. a canvas, able to receive keyboard focus and therefore `keydown` events.
+    /// Does nothing if it already has a tab index.
+    ///
+    pub fn make_focusable(elem: &web_sys::HtmlElement) {
+        if !elem.has_attribute("tabindex") {
+            elem.set_tab_index(0);
+        }
+    }
+
+    ///
+    /// Make an element focusable with [`make_focusable`] and give it keyboard focus.
+    ///
+    pub fn focus(elem: &web_sys::HtmlElement) {
+        make_focusable(elem);
+        elem.focus().unwrap_throw();
+    }
+
+    ///
+    /// The ratio of physical pixels to css pixels. Only available on the main thread,
+    /// so it has to be sent to the worker if it needs it.
+    ///
+    pub fn device_pixel_ratio() -> f64 {
+        gloo::utils::window().device_pixel_ratio()
+    }
+
+    ///
+    /// Size the canvas's backing store in physical pixels so it isn't blurry on high-dpi displays,
+    /// keeping its css size the same. Returns the new backing size.
+    ///
+    /// Call before transferring the canvas to the worker. Afterwards the canvas can't be
+    /// resized from the main thread, so send the size through
+    /// [`EngineMain::resize`](crate::EngineMain::resize) instead.
+    ///
+    pub fn configure_hidpi(canvas: &web_sys::HtmlCanvasElement) -> (u32, u32) {
+        let ratio = device_pixel_ratio();
+        let rect = canvas.get_bounding_client_rect();
+
+        let style = canvas.style();
+        style
+            .set_property("width", &format!("{}px", rect.width()))
+            .unwrap_throw();
+        style
+            .set_property("height", &format!("{}px", rect.height()))
+            .unwrap_throw();
+
+        let width = (rect.width() * ratio).round() as u32;
+        let height = (rect.height() * ratio).round() as u32;
+        if canvas.width() != width {
+            canvas.set_width(width);
+        }
+        if canvas.height() != height {
+            canvas.set_height(height);
+        }
+        (width, height)
+    }
+    ///
+    /// The attributes a webgl2 context is created with.
+    /// See [`get_context_webgl2_offscreen_with_attrs`].
+    ///
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct ContextAttributes {
+        pub antialias: bool,
+        pub alpha: bool,
+        pub premultiplied_alpha: bool,
+        pub preserve_drawing_buffer: bool,
+        pub depth: bool,
+        pub stencil: bool,
+    }
+
+    impl Default for ContextAttributes {
+        fn default() -> Self {
+            ContextAttributes {
+                antialias: true,
+                alpha: true,
+                premultiplied_alpha: true,
+                preserve_drawing_buffer: false,
+                depth: true,
+                stencil: false,
+            }
+        }
+    }
+
+    impl ContextAttributes {
+        fn to_object(self) -> js_sys::Object {
+            let options = js_sys::Object::new();
+            let set = |key: &str, val: bool| {
+                js_sys::Reflect::set(&options, &JsValue::from_str(key), &JsValue::from_bool(val))
+                    .unwrap_throw();
+            };
+            set("antialias", self.antialias);
+            set("alpha", self.alpha);
+            set("premultipliedAlpha", self.premultiplied_alpha);
+            set("preserveDrawingBuffer", self.preserve_drawing_buffer);
+            set("depth", self.depth);
+            set("stencil", self.stencil);
+            options
+        }
+    }
+
+    ///
+    /// Get a webgl2 context for an offscreen canvas element, with the default
+    /// [`ContextAttributes`] (antialiased).
     ///
     pub fn get_context_webgl2_offscreen(
         canvas: &web_sys::OffscreenCanvas,
+    ) -> web_sys::WebGl2RenderingContext {
+        get_context_webgl2_offscreen_with_attrs(canvas, &ContextAttributes::default())
+    }
+
+    ///
+    /// Same as [`get_context_webgl2_offscreen`] but with custom [`ContextAttributes`].
+    ///
+    pub fn get_context_webgl2_offscreen_with_attrs(
+        canvas: &web_sys::OffscreenCanvas,
+        attrs: &ContextAttributes,
+    ) -> web_sys::WebGl2RenderingContext {
+        canvas
+            .get_context_with_context_options("webgl2", &attrs.to_object())
+            .unwrap_throw()
+            .unwrap_throw()
+            .dyn_into()
+            .unwrap_throw()
+    }
+
+    ///
+    /// Same as [`get_context_webgl2_offscreen`] but the context has a stencil buffer,
+    /// as needed by [`simple2d::CtxWrap::begin_stencil_write`](crate::simple2d::CtxWrap::begin_stencil_write).
+    ///
+    pub fn get_context_webgl2_offscreen_with_stencil(
+        canvas: &web_sys::OffscreenCanvas,
+    ) -> web_sys::WebGl2RenderingContext {
+        let attrs = ContextAttributes {
+            stencil: true,
+            ..ContextAttributes::default()
+        };
+        get_context_webgl2_offscreen_with_attrs(canvas, &attrs)
+    }
+
+    ///
+    /// Get a webgl2 context for a regular canvas element.
+    /// Used with [`SingleThreadedEngine`](crate::SingleThreadedEngine).
+    ///
+    pub fn get_context_webgl2(
+        canvas: &web_sys::HtmlCanvasElement,
     ) -> web_sys::WebGl2RenderingContext {
         canvas
             .get_context("webgl2")
@@ -53,12 +192,58 @@ pub mod utils {
             .unwrap_throw()
     }
 
+    ///
+    /// Returns true if canvases can be transferred to a worker, i.e. if [`EngineMain`](crate::EngineMain)
+    /// can be used. Otherwise fall back to [`SingleThreadedEngine`](crate::SingleThreadedEngine).
+    ///
+    pub fn supports_offscreen() -> bool {
+        let global = js_sys::global();
+        let has = |obj: &JsValue, name: &str| {
+            js_sys::Reflect::has(obj, &JsValue::from_str(name)).unwrap_or(false)
+        };
+
+        if !has(&global, "OffscreenCanvas") {
+            return false;
+        }
+        js_sys::Reflect::get(&global, &JsValue::from_str("HTMLCanvasElement"))
+            .and_then(|c| js_sys::Reflect::get(&c, &JsValue::from_str("prototype")))
+            .map(|p| has(&p, "transferControlToOffscreen"))
+            .unwrap_or(false)
+    }
+
     ///
     /// Get the worker global scope. Call from within a webworker.
     ///
     pub fn get_worker_global_context() -> web_sys::DedicatedWorkerGlobalScope {
         js_sys::global().dyn_into().unwrap_throw()
     }
+
+    fn lose_context_ext(
+        context: &web_sys::WebGl2RenderingContext,
+    ) -> Result<web_sys::WebglLoseContext, String> {
+        context
+            .get_extension("WEBGL_lose_context")
+            .map_err(|_| "failed to query WEBGL_lose_context".to_string())?
+            .ok_or_else(|| "WEBGL_lose_context is not available".to_string())
+            .map(|a| a.unchecked_into())
+    }
+
+    ///
+    /// Simulate a loss of the webgl2 context using the `WEBGL_lose_context` extension.
+    /// Useful for testing context loss recovery.
+    ///
+    pub fn lose_context(context: &web_sys::WebGl2RenderingContext) -> Result<(), String> {
+        lose_context_ext(context)?.lose_context();
+        Ok(())
+    }
+
+    ///
+    /// Restore a context previously lost through [`lose_context`].
+    ///
+    pub fn restore_context(context: &web_sys::WebGl2RenderingContext) -> Result<(), String> {
+        lose_context_ext(context)?.restore_context();
+        Ok(())
+    }
 }
 
 #[wasm_bindgen]
@@ -68,37 +253,154 @@ extern "C" {
     static performance: web_sys::Performance;
 }
 
+///
+/// Where a [`FrameTimer`] gets the current time from and how it waits.
+/// Times are in milliseconds.
+///
+pub trait TimeSource {
+    fn now(&self) -> f64;
+    fn sleep(&self, ms: u32) -> futures::future::LocalBoxFuture<'static, ()>;
+}
+
+///
+/// The default [`TimeSource`]. Uses `performance.now()` and real timeouts.
+///
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PerformanceTimeSource;
+
+impl TimeSource for PerformanceTimeSource {
+    fn now(&self) -> f64 {
+        performance.now()
+    }
+    fn sleep(&self, ms: u32) -> futures::future::LocalBoxFuture<'static, ()> {
+        Box::pin(TimeoutFuture::new(ms))
+    }
+}
+
+///
+/// A [`TimeSource`] that only moves when told to, for stepping a [`FrameTimer`]
+/// deterministically in tests. Sleeping advances the clock instantly instead of waiting.
+/// Clones share the same clock.
+///
+#[derive(Debug, Clone, Default)]
+pub struct ManualTimeSource {
+    now: Rc<std::cell::Cell<f64>>,
+}
+
+impl ManualTimeSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// Move the clock forward, for example to simulate time spent handling a frame.
+    ///
+    pub fn advance(&self, ms: f64) {
+        self.now.set(self.now.get() + ms);
+    }
+}
+
+impl TimeSource for ManualTimeSource {
+    fn now(&self) -> f64 {
+        self.now.get()
+    }
+    fn sleep(&self, ms: u32) -> futures::future::LocalBoxFuture<'static, ()> {
+        self.advance(ms as f64);
+        Box::pin(futures::future::ready(()))
+    }
+}
+
 struct Timer {
+    source: Box<dyn TimeSource>,
     last: f64,
+    last_frame: f64,
+    delta: f64,
+    //The time between the start of the previous frame and this one.
+    diff: f64,
+    //The time between the previous frame being returned and the start of this one.
+    work: f64,
     frame_rate: usize,
+    //The start time and timeout of a frame that hasn't finished yet.
+    //Kept across calls so that dropping a call to next() doesn't
+    //schedule a second timeout.
+    pending: Option<(f64, futures::future::LocalBoxFuture<'static, ()>)>,
 }
 impl Timer {
-    fn new(frame_rate: usize) -> Timer {
+    //Convert frames per second into milliseconds per frame.
+    fn budget(frame_rate: usize) -> usize {
         let frame_rate = ((1.0 / frame_rate as f64) * 1000.0).round() as usize;
-
         assert!(frame_rate > 0);
-        //let window = gloo::utils::window();
-        //let performance = window.performance().unwrap_throw();
+        frame_rate
+    }
+
+    fn new(frame_rate: usize, source: Box<dyn TimeSource>) -> Timer {
+        let frame_rate = Self::budget(frame_rate);
 
+        let now = source.now();
         Timer {
-            last: performance.now(),
+            source,
+            last: now,
+            last_frame: now,
+            delta: 0.0,
+            diff: 0.0,
+            work: 0.0,
             frame_rate,
+            pending: None,
         }
     }
 
-    async fn next(&mut self) {
-        //let window = gloo::utils::window();
-        //let performance = window.performance().unwrap_throw();
+    ///
+    /// A frame that is already waiting keeps its old budget.
+    ///
+    fn set_frame_rate(&mut self, frame_rate: usize) {
+        self.frame_rate = Self::budget(frame_rate);
+    }
 
-        let tt = performance.now();
-        let diff = performance.now() - self.last;
+    ///
+    /// Cancel the pending timeout, if any.
+    ///
+    fn cancel(&mut self) {
+        self.pending = None;
+    }
 
-        if self.frame_rate as f64 - diff > 0.0 {
-            let d = (self.frame_rate as f64 - diff) as usize;
-            TimeoutFuture::new(d.try_into().unwrap_throw()).await;
-        }
+    ///
+    /// Start timing from now, so the next frame doesn't report the time spent paused.
+    ///
+    fn reset(&mut self) {
+        self.cancel();
+        let now = self.source.now();
+        self.last = now;
+        self.last_frame = now;
+    }
+
+    async fn next(&mut self) {
+        let tt = match &mut self.pending {
+            Some((tt, timeout)) => {
+                timeout.await;
+                *tt
+            }
+            None => {
+                let tt = self.source.now();
+                let diff = self.source.now() - self.last;
+                self.diff = diff;
+                self.work = tt - self.last_frame;
+
+                if self.frame_rate as f64 - diff > 0.0 {
+                    let d = (self.frame_rate as f64 - diff) as usize;
+                    let timeout = self.source.sleep(d.try_into().unwrap_throw());
+                    let (_, timeout) = self.pending.insert((tt, timeout));
+                    timeout.await;
+                }
+                tt
+            }
+        };
+        self.pending = None;
 
         self.last = tt;
+
+        let now = self.source.now();
+        self.delta = now - self.last_frame;
+        self.last_frame = now;
     }
 }
 
@@ -106,6 +408,52 @@ use futures::FutureExt;
 use futures::Stream;
 use futures::StreamExt;
 
+#[derive(Default)]
+struct PauseState {
+    paused: bool,
+    waiting: Vec<futures::channel::oneshot::Sender<()>>,
+}
+
+///
+/// A shared pause flag. While paused, a [`FrameTimer`] it is attached to
+/// through [`FrameTimer::set_pause`] stops producing frames.
+///
+#[derive(Clone, Default)]
+pub struct Pause {
+    inner: Rc<RefCell<PauseState>>,
+}
+
+impl Pause {
+    pub fn pause(&self) {
+        self.inner.borrow_mut().paused = true;
+    }
+
+    pub fn resume(&self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.paused = false;
+        for w in inner.waiting.drain(..) {
+            let _ = w.send(());
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.inner.borrow().paused
+    }
+
+    async fn wait_resumed(&self) {
+        let r = {
+            let mut inner = self.inner.borrow_mut();
+            if !inner.paused {
+                return;
+            }
+            let (s, r) = futures::channel::oneshot::channel();
+            inner.waiting.push(s);
+            r
+        };
+        let _ = r.await;
+    }
+}
+
 ///
 /// Takes a stream, and continually returns a list of its items that have accumulated over
 /// the specified period.
@@ -114,123 +462,1609 @@ pub struct FrameTimer<T, K> {
     timer: Timer,
     buffer: Vec<T>,
     stream: K,
+    redraw: bool,
+    max_queued: Option<usize>,
+    dropped: usize,
+    pause: Option<Pause>,
+    stats: FrameStats,
+    work_history: std::collections::VecDeque<f64>,
+    overrun_handler: Option<Box<dyn FnMut(f64)>>,
+}
+
+///
+/// Timing information about the last frame returned by a [`FrameTimer`].
+/// All times are in milliseconds.
+///
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct FrameStats {
+    /// The time since the previous frame was returned.
+    pub delta: f64,
+    /// The time between the previous frame being returned and the timer being
+    /// waited on again, i.e. the time spent handling the previous frame.
+    pub work: f64,
+    /// The number of items returned with the frame.
+    pub events: usize,
+    /// The average of `work` over the last 60 frames.
+    pub average_work: f64,
 }
+
 impl<T, K: Stream<Item = T> + std::marker::Unpin> FrameTimer<T, K> {
     pub fn new(frame_rate: usize, stream: K) -> Self {
+        Self::new_with_source(frame_rate, stream, PerformanceTimeSource)
+    }
+
+    ///
+    /// Same as [`FrameTimer::new`] but with a custom [`TimeSource`],
+    /// such as a [`ManualTimeSource`].
+    ///
+    pub fn new_with_source(
+        frame_rate: usize,
+        stream: K,
+        source: impl TimeSource + 'static,
+    ) -> Self {
         FrameTimer {
-            timer: Timer::new(frame_rate),
+            timer: Timer::new(frame_rate, Box::new(source)),
             buffer: vec![],
             stream,
+            redraw: true,
+            max_queued: None,
+            dropped: 0,
+            pause: None,
+            stats: FrameStats::default(),
+            work_history: std::collections::VecDeque::new(),
+            overrun_handler: None,
         }
     }
-    pub async fn next(&mut self) -> &[T] {
-        self.buffer.clear();
-        loop {
-            futures::select_biased!(
-                _ = self.timer.next().fuse() =>{
-                    break;
-                },
-                val = self.stream.next().fuse()=>{
-                    self.buffer.push(val.unwrap_throw());
-                }
-            )
-        }
-        &self.buffer
+
+    ///
+    /// Attach a pause flag, for example from [`EngineWorker::pause_handle`].
+    /// While it is paused, [`FrameTimer::next`] doesn't return.
+    /// On resume, the timer restarts so the first frame doesn't report the time spent paused.
+    ///
+    pub fn set_pause(&mut self, pause: Pause) {
+        self.pause = Some(pause);
     }
-}
 
-pub use main::EngineMain;
-use std::marker::PhantomData;
-mod main {
-    use super::*;
     ///
-    /// The component of the engine that runs on the main thread.
+    /// Change the frame rate. A frame that is already waiting finishes with the old rate,
+    /// the new rate applies from the next call to [`FrameTimer::next`].
     ///
-    pub struct EngineMain<MW, WM> {
-        worker: std::rc::Rc<std::cell::RefCell<web_sys::Worker>>,
-        _handle: gloo::events::EventListener,
-        _p: PhantomData<(MW, WM)>,
+    pub fn set_frame_rate(&mut self, frame_rate: usize) {
+        self.timer.set_frame_rate(frame_rate);
     }
 
-    impl<MW: 'static + Serialize, WM: for<'a> Deserialize<'a> + 'static> EngineMain<MW, WM> {
-        ///
-        /// Create the engine. Blocks until the worker thread reports that
-        /// it is ready to receive the offscreen canvas.
-        ///
-        pub async fn new(
-            web_worker_url: &str,
-            canvas: web_sys::OffscreenCanvas,
-        ) -> (Self, futures::channel::mpsc::UnboundedReceiver<WM>) {
-            let mut options = web_sys::WorkerOptions::new();
-            options.type_(web_sys::WorkerType::Module);
-            let worker = Rc::new(RefCell::new(
-                web_sys::Worker::new_with_options(web_worker_url, &options).unwrap_throw(),
-            ));
+    ///
+    /// Limit how many items are returned per frame. When more arrive, the oldest are dropped
+    /// and counted in [`FrameTimer::dropped_event_count`]. `None` (the default) means no limit.
+    ///
+    pub fn set_max_queued(&mut self, max_queued: Option<usize>) {
+        assert_ne!(max_queued, Some(0));
+        self.max_queued = max_queued;
+    }
 
-            let (fs, fr) = futures::channel::oneshot::channel();
-            let mut fs = Some(fs);
+    ///
+    /// The total number of items dropped because of [`FrameTimer::set_max_queued`].
+    ///
+    pub fn dropped_event_count(&self) -> usize {
+        self.dropped
+    }
 
-            let (ks, kr) = futures::channel::mpsc::unbounded();
-            let _handle =
-                gloo::events::EventListener::new(&worker.borrow(), "message", move |event| {
-                    //log!("waaa");
-                    let event = event.dyn_ref::<web_sys::MessageEvent>().unwrap_throw();
-                    let data = event.data();
+    ///
+    /// Call `func` with the overrun in milliseconds whenever a frame took longer than the
+    /// frame budget, e.g. to lower the quality when the device can't keep up.
+    /// Called while waiting for the next frame, before it is returned.
+    ///
+    pub fn set_overrun_handler(&mut self, func: impl FnMut(f64) + 'static) {
+        self.overrun_handler = Some(Box::new(func));
+    }
 
-                    let data: js_sys::Array = data.dyn_into().unwrap_throw();
-                    let m = data.get(0);
-                    let k = data.get(1);
+    ///
+    /// Timing information about the last frame that was returned.
+    ///
+    pub fn frame_stats(&self) -> FrameStats {
+        self.stats
+    }
 
-                    if !m.is_null() {
-                        if let Some(s) = m.as_string() {
-                            if s == "ready" {
-                                if let Some(f) = fs.take() {
-                                    f.send(()).unwrap_throw();
-                                }
-                            }
-                        }
-                    } else {
-                        let a = k.into_serde().unwrap_throw();
-                        ks.unbounded_send(a).unwrap_throw();
-                    }
-                });
+    fn record_stats(&mut self) {
+        const HISTORY: usize = 60;
 
-            let _ = fr.await.unwrap_throw();
+        if self.work_history.len() == HISTORY {
+            self.work_history.pop_front();
+        }
+        self.work_history.push_back(self.timer.work);
 
-            let arr = js_sys::Array::new_with_length(1);
-            arr.set(0, canvas.clone().into());
+        self.stats = FrameStats {
+            delta: self.timer.delta,
+            work: self.timer.work,
+            events: self.buffer.len(),
+            average_work: self.work_history.iter().sum::<f64>() / self.work_history.len() as f64,
+        };
 
-            let data = js_sys::Array::new();
-            data.set(0, canvas.into());
-            data.set(1, JsValue::null());
+        let overrun = self.timer.diff - self.timer.frame_rate as f64;
+        if overrun > 0.0 {
+            if let Some(func) = &mut self.overrun_handler {
+                func(overrun);
+            }
+        }
+    }
+    ///
+    /// Wait for the next frame and return the items that accumulated over it.
+    /// Returns `None` once the stream has ended, for example after [`EngineMain::shutdown`].
+    ///
+    pub async fn next(&mut self) -> Option<&[T]> {
+        if !self.fill().await {
+            return None;
+        }
+        Some(&self.buffer)
+    }
 
-            worker
-                .borrow()
-                .post_message_with_transfer(&data, &arr)
-                .unwrap_throw();
+    ///
+    /// Like [`FrameTimer::next`] but also returns whether the previous frame
+    /// took longer than the frame budget.
+    ///
+    pub async fn next_with_deadline(&mut self) -> Option<(&[T], bool)> {
+        if !self.fill().await {
+            return None;
+        }
+        let overran = self.timer.diff > self.timer.frame_rate as f64;
+        Some((&self.buffer, overran))
+    }
 
-            (
-                EngineMain {
-                    worker,
-                    _handle,
-                    _p: PhantomData,
-                },
-                kr,
-            )
+    ///
+    /// Like [`FrameTimer::next`] but also returns the milliseconds elapsed
+    /// since the previous frame was returned.
+    ///
+    pub async fn next_timed(&mut self) -> Option<(&[T], f64)> {
+        if !self.fill().await {
+            return None;
+        }
+        Some((&self.buffer, self.timer.delta))
+    }
+    ///
+    /// Return the items that have already arrived, without waiting for the next frame.
+    /// Useful when not rendering at a fixed rate, e.g. during a loading screen.
+    /// The returned items are not returned again by [`FrameTimer::next`].
+    ///
+    pub fn poll_events(&mut self) -> &[T] {
+        self.buffer.clear();
+        while let Some(Some(val)) = self.stream.next().now_or_never() {
+            self.buffer.push(val);
         }
+        self.trim(0);
+        &self.buffer
+    }
+    ///
+    /// Mark that the next frame should be drawn even if no items arrive.
+    /// See [`FrameTimer::next_redraw`].
+    ///
+    pub fn request_redraw(&mut self) {
+        self.redraw = true;
+    }
 
-        pub fn post_message(&mut self, val: MW) {
-            let a = JsValue::from_serde(&val).unwrap_throw();
+    ///
+    /// Like [`FrameTimer::next`] but skips frames where no items arrived and
+    /// no redraw was requested through [`FrameTimer::request_redraw`].
+    /// The first frame is always returned.
+    ///
+    /// For continuous animations, call [`FrameTimer::request_redraw`] every frame
+    /// while the animation is running.
+    ///
+    pub async fn next_redraw(&mut self) -> Option<&[T]> {
+        loop {
+            if !self.fill().await {
+                return None;
+            }
+            //Clear the request first, so it isn't skipped when items arrived.
+            if std::mem::replace(&mut self.redraw, false) || !self.buffer.is_empty() {
+                break;
+            }
+        }
+        Some(&self.buffer)
+    }
 
-            let data = js_sys::Array::new();
-            data.set(0, JsValue::null());
-            data.set(1, a);
+    ///
+    /// Cancel the timeout of a frame that is still pending.
+    /// The next call to [`FrameTimer::next`] starts a fresh frame.
+    ///
+    pub fn cancel_pending(&mut self) {
+        self.timer.cancel();
+    }
 
-            self.worker.borrow().post_message(&data).unwrap_throw();
-        }
+    ///
+    /// Turn this timer into a stream of owned [`Frame`]s, as an alternative to calling
+    /// [`FrameTimer::next`] in a loop.
+    ///
+    pub fn frames(self) -> impl Stream<Item = Frame<T>> {
+        futures::stream::unfold(self, |mut ft| async move {
+            if !ft.fill().await {
+                return None;
+            }
+            let frame = Frame {
+                events: std::mem::take(&mut ft.buffer),
+                delta: ft.timer.delta,
+            };
+            Some((frame, ft))
+        })
+    }
+
+    //Returns false if the stream ended.
+    async fn fill(&mut self) -> bool {
+        self.buffer.clear();
+
+        if let Some(pause) = self.pause.clone() {
+            if pause.is_paused() {
+                self.timer.cancel();
+                let resumed = pause.wait_resumed().fuse();
+                futures::pin_mut!(resumed);
+                loop {
+                    futures::select_biased!(
+                        _ = resumed =>{
+                            break;
+                        },
+                        val = self.stream.next().fuse()=>{
+                            match val {
+                                Some(val) => self.buffer.push(val),
+                                None => return false,
+                            }
+                            self.trim(self.max_queued.unwrap_or(0));
+                        }
+                    )
+                }
+                self.timer.reset();
+            }
+        }
+
+        loop {
+            futures::select_biased!(
+                _ = self.timer.next().fuse() =>{
+                    //A timer that is already done wins the biased select, so pick up
+                    //anything the stream has ready before ending the frame.
+                    while let Some(val) = self.stream.next().now_or_never() {
+                        match val {
+                            Some(val) => self.buffer.push(val),
+                            None if self.buffer.is_empty() => return false,
+                            None => break,
+                        }
+                    }
+                    self.trim(0);
+                    self.record_stats();
+                    return true;
+                },
+                val = self.stream.next().fuse()=>{
+                    match val {
+                        Some(val) => self.buffer.push(val),
+                        None => return false,
+                    }
+                    //Trim in bulk so that dropping isn't quadratic.
+                    self.trim(self.max_queued.unwrap_or(0));
+                }
+            )
+        }
+    }
+
+    //Drop the oldest items if the buffer has more than max_queued plus slack.
+    fn trim(&mut self, slack: usize) {
+        if let Some(max) = self.max_queued {
+            if self.buffer.len() > max + slack {
+                let n = self.buffer.len() - max;
+                self.buffer.drain(..n);
+                self.dropped += n;
+            }
+        }
+    }
+}
+
+///
+/// A frame yielded by [`FrameTimer::frames`].
+///
+pub struct Frame<T> {
+    /// The items that accumulated over this frame.
+    pub events: Vec<T>,
+    /// Milliseconds since the previous frame.
+    pub delta: f64,
+}
+
+//Messages that came with transferred buffers, kept apart from the message stream.
+type Transferred<T> = Rc<RefCell<Vec<(T, Vec<js_sys::ArrayBuffer>)>>>;
+
+///
+/// Every message sent between the main thread and the worker is a two element
+/// array of `[control, payload]`. A null control slot means the payload is a user message.
+/// Messages that transfer buffers carry them in an optional third slot.
+///
+fn frame(control: JsValue, payload: JsValue) -> js_sys::Array {
+    let data = js_sys::Array::new();
+    data.set(0, control);
+    data.set(1, payload);
+    data
+}
+
+pub use main::{EngineError, EngineMain, EventHandle, MainOptions, Reply};
+use std::marker::PhantomData;
+mod main {
+    use super::*;
+    use std::collections::HashMap;
+    use std::collections::HashSet;
+
+    #[derive(Default)]
+    struct Checkpoints {
+        reached: HashSet<String>,
+        waiting: HashMap<String, Vec<futures::channel::oneshot::Sender<()>>>,
+    }
+
+    impl Checkpoints {
+        fn reach(&mut self, name: String) {
+            if let Some(waiting) = self.waiting.remove(&name) {
+                for w in waiting {
+                    let _ = w.send(());
+                }
+            }
+            self.reached.insert(name);
+        }
+
+        fn wait(&mut self, name: &str) -> Option<futures::channel::oneshot::Receiver<()>> {
+            if self.reached.contains(name) {
+                return None;
+            }
+            let (s, r) = futures::channel::oneshot::channel();
+            self.waiting.entry(name.to_string()).or_default().push(s);
+            Some(r)
+        }
+    }
+
+    //A one time signal from the worker that can be waited on any number of times.
+    //Fails instead if the worker dies before sending it.
+    #[derive(Default)]
+    struct Signal {
+        result: Option<Result<(), EngineError>>,
+        waiting: Vec<futures::channel::oneshot::Sender<Result<(), EngineError>>>,
+    }
+
+    impl Signal {
+        fn fire(&mut self) {
+            self.finish(Ok(()));
+        }
+
+        fn fail(&mut self, e: EngineError) {
+            self.finish(Err(e));
+        }
+
+        fn finish(&mut self, result: Result<(), EngineError>) {
+            if self.result.is_some() {
+                return;
+            }
+            for w in self.waiting.drain(..) {
+                let _ = w.send(result.clone());
+            }
+            self.result = Some(result);
+        }
+
+        fn wait(&mut self) -> impl std::future::Future<Output = Result<(), EngineError>> {
+            let r = match &self.result {
+                Some(result) => Err(result.clone()),
+                None => {
+                    let (s, r) = futures::channel::oneshot::channel();
+                    self.waiting.push(s);
+                    Ok(r)
+                }
+            };
+            async move {
+                match r {
+                    Ok(r) => r.await.unwrap_throw(),
+                    Err(result) => result,
+                }
+            }
+        }
+    }
+
+    ///
+    /// Errors that can happen while creating an [`EngineMain`] or waiting on its worker.
+    ///
+    #[derive(Debug, Clone)]
+    pub enum EngineError {
+        /// The worker could not be constructed, e.g. the script failed to load
+        /// or module workers are not supported.
+        WorkerCreation(JsValue),
+        /// A message from the worker could not be deserialized.
+        Serialization(String),
+        /// The canvas could not be transferred to the worker.
+        Transfer(JsValue),
+        /// The worker did not report that it was ready within [`MainOptions::ready_timeout`].
+        ReadyTimeout,
+        /// The worker panicked, with the panic message. See [`EngineMain::worker_error`].
+        WorkerPanicked(String),
+        /// The worker shut down, e.g. through [`EngineMain::shutdown`], before responding.
+        Closed,
+    }
+
+    impl std::fmt::Display for EngineError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                EngineError::WorkerCreation(e) => write!(f, "failed to create worker: {:?}", e),
+                EngineError::Serialization(e) => write!(f, "failed to deserialize: {}", e),
+                EngineError::Transfer(e) => write!(f, "failed to transfer canvas: {:?}", e),
+                EngineError::ReadyTimeout => write!(f, "timed out waiting for the worker"),
+                EngineError::WorkerPanicked(e) => write!(f, "the worker panicked: {}", e),
+                EngineError::Closed => write!(f, "the worker shut down"),
+            }
+        }
+    }
+
+    impl std::error::Error for EngineError {}
+
+    ///
+    /// Options for [`EngineMain::new_with_options`].
+    ///
+    pub struct MainOptions {
+        /// Defaults to [`web_sys::WorkerType::Module`].
+        pub worker_type: web_sys::WorkerType,
+        /// How many milliseconds to wait for the worker to report that it is ready before
+        /// giving up with [`EngineError::ReadyTimeout`]. Defaults to waiting forever.
+        pub ready_timeout: Option<u32>,
+    }
+
+    impl Default for MainOptions {
+        fn default() -> Self {
+            MainOptions {
+                worker_type: web_sys::WorkerType::Module,
+                ready_timeout: None,
+            }
+        }
+    }
+
+    type Pending<WM> =
+        Rc<RefCell<HashMap<u64, futures::channel::oneshot::Sender<Result<WM, EngineError>>>>>;
+
+    type Captures =
+        Rc<RefCell<Vec<futures::channel::oneshot::Sender<Result<web_sys::Blob, EngineError>>>>>;
+
+    //Values queued by register_event_batched, shared by all batched listeners.
+    #[derive(Default)]
+    struct Batch {
+        queue: Vec<JsValue>,
+        scheduled: bool,
+        //Kept so the request isn't cancelled. Only replaced once it has fired.
+        frame: Option<gloo::render::AnimationFrame>,
+    }
+
+    fn flush_batch(worker: &web_sys::Worker, batch: &mut Batch) {
+        if batch.queue.is_empty() {
+            return;
+        }
+        let arr: js_sys::Array = batch.queue.drain(..).collect();
+        worker
+            .post_message(&frame(JsValue::from_str("batch"), arr.into()))
+            .unwrap_throw();
+    }
+
+    //Everything the main thread posts to the worker goes through here, so that
+    //batched values still waiting for their animation frame are posted first.
+    #[derive(Clone)]
+    struct Outbox {
+        worker: Rc<RefCell<web_sys::Worker>>,
+        batch: Rc<RefCell<Batch>>,
+    }
+
+    impl Outbox {
+        fn post(&self, data: &js_sys::Array) {
+            let worker = self.worker.borrow();
+            flush_batch(&worker, &mut self.batch.borrow_mut());
+            worker.post_message(data).unwrap_throw();
+        }
+
+        fn post_with_transfer(&self, data: &js_sys::Array, transfer: &js_sys::Array) {
+            let worker = self.worker.borrow();
+            flush_batch(&worker, &mut self.batch.borrow_mut());
+            worker.post_message_with_transfer(data, transfer).unwrap_throw();
+        }
+
+        fn terminate(&self) {
+            self.worker.borrow().terminate();
+        }
+    }
+
+    //Each subscriber returns false once its receiver has been dropped.
+    type Subscribers = Rc<RefCell<HashMap<String, Vec<Box<dyn FnMut(&JsValue) -> bool>>>>>;
+
+    ///
+    /// A reply to a [`EngineMain::request`]. Dropping it before it resolves
+    /// forgets the request so a late reply is ignored.
+    ///
+    pub struct Reply<WM> {
+        id: u64,
+        pending: Pending<WM>,
+        recv: futures::channel::oneshot::Receiver<Result<WM, EngineError>>,
+    }
+
+    impl<WM> std::future::Future for Reply<WM> {
+        type Output = Result<WM, EngineError>;
+        fn poll(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Self::Output> {
+            self.recv.poll_unpin(cx).map(|a| a.unwrap_throw())
+        }
+    }
+
+    impl<WM> Drop for Reply<WM> {
+        fn drop(&mut self) {
+            self.pending.borrow_mut().remove(&self.id);
+        }
+    }
+
+    ///
+    /// The component of the engine that runs on the main thread.
+    ///
+    /// Messages sent through [`EngineMain::post_message`] and through listeners
+    /// created by [`EngineMain::register_event`] all go through the worker's single
+    /// message port, so the worker receives them in the order they were posted,
+    /// which for dom events is the order the browser dispatched them in.
+    /// No sequence numbers are needed to restore the order on the worker.
+    /// Values from [`EngineMain::register_event_batched`] are held until the next animation
+    /// frame, but are posted before anything else the main thread sends in the meantime,
+    /// so they keep their place in the order too.
+    /// The same holds for control messages sent from the worker back to main.
+    ///
+    /// Startup goes through three signals, always in this order:
+    /// "ready" (the worker started, awaited by [`EngineMain::new`]),
+    /// "initialized" (gl is set up, see [`EngineMain::wait_initialized`]) and
+    /// "first frame" (something is on screen, see [`EngineMain::wait_first_frame`]).
+    /// The last two are only sent if the worker calls the matching methods, in that order.
+    ///
+    pub struct EngineMain<MW, WM> {
+        worker: Outbox,
+        _handle: gloo::events::EventListener,
+        capabilities: Option<WorkerCapabilities>,
+        checkpoints: Rc<RefCell<Checkpoints>>,
+        initialized: Rc<RefCell<Signal>>,
+        first_frame: Rc<RefCell<Signal>>,
+        error: Rc<RefCell<Option<String>>>,
+        transferred: Transferred<WM>,
+        captures: Captures,
+        pending: Pending<WM>,
+        subscribers: Subscribers,
+        next_id: u64,
+        closed: futures::channel::oneshot::Receiver<()>,
+        events: Vec<(EventHandle, gloo::events::EventListener)>,
+        next_event: u64,
+        _p: PhantomData<(MW, WM)>,
+    }
+
+    ///
+    /// Identifies a listener stored by [`EngineMain::register_event_owned`].
+    ///
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+    pub struct EventHandle(u64);
+
+    impl<MW: 'static + Serialize, WM: for<'a> Deserialize<'a> + 'static> EngineMain<MW, WM> {
+        ///
+        /// Create the engine. Blocks until the worker thread reports that
+        /// it is ready to receive the offscreen canvas.
+        ///
+        pub async fn new(
+            web_worker_url: &str,
+            canvas: web_sys::OffscreenCanvas,
+        ) -> Result<(Self, futures::channel::mpsc::UnboundedReceiver<WM>), EngineError> {
+            Self::new_with_options(web_worker_url, canvas, MainOptions::default()).await
+        }
+
+        ///
+        /// Same as [`EngineMain::new`] but throws on error.
+        ///
+        pub async fn new_or_throw(
+            web_worker_url: &str,
+            canvas: web_sys::OffscreenCanvas,
+        ) -> (Self, futures::channel::mpsc::UnboundedReceiver<WM>) {
+            Self::new(web_worker_url, canvas).await.unwrap_throw()
+        }
+
+        ///
+        /// Same as [`EngineMain::new`] but with additional [`MainOptions`].
+        ///
+        pub async fn new_with_options(
+            web_worker_url: &str,
+            canvas: web_sys::OffscreenCanvas,
+            main_options: MainOptions,
+        ) -> Result<(Self, futures::channel::mpsc::UnboundedReceiver<WM>), EngineError> {
+            Self::new_multi_with_options(web_worker_url, vec![canvas], main_options).await
+        }
+
+        ///
+        /// Same as [`EngineMain::new`] but transfers several canvases, e.g. a main view and a minimap.
+        /// The worker gets them through [`EngineWorker::canvas_at`], in the same order.
+        /// Panics if `canvases` is empty.
+        ///
+        pub async fn new_multi(
+            web_worker_url: &str,
+            canvases: Vec<web_sys::OffscreenCanvas>,
+        ) -> Result<(Self, futures::channel::mpsc::UnboundedReceiver<WM>), EngineError> {
+            Self::new_multi_with_options(web_worker_url, canvases, MainOptions::default()).await
+        }
+
+        ///
+        /// Same as [`EngineMain::new_multi`] but with additional [`MainOptions`].
+        ///
+        pub async fn new_multi_with_options(
+            web_worker_url: &str,
+            canvases: Vec<web_sys::OffscreenCanvas>,
+            main_options: MainOptions,
+        ) -> Result<(Self, futures::channel::mpsc::UnboundedReceiver<WM>), EngineError> {
+            assert!(!canvases.is_empty(), "at least one canvas is needed");
+
+            let mut options = web_sys::WorkerOptions::new();
+            options.type_(main_options.worker_type);
+            let worker = Rc::new(RefCell::new(
+                web_sys::Worker::new_with_options(web_worker_url, &options)
+                    .map_err(EngineError::WorkerCreation)?,
+            ));
+
+            let (fs, fr) = futures::channel::oneshot::channel();
+            let mut fs = Some(fs);
+
+            let checkpoints = Rc::new(RefCell::new(Checkpoints::default()));
+            let cc = checkpoints.clone();
+
+            let initialized = Rc::new(RefCell::new(Signal::default()));
+            let ii = initialized.clone();
+
+            let first_frame = Rc::new(RefCell::new(Signal::default()));
+            let ff = first_frame.clone();
+
+            let captures: Captures = Rc::new(RefCell::new(Vec::new()));
+            let caps = captures.clone();
+
+            let pending: Pending<WM> = Rc::new(RefCell::new(HashMap::new()));
+            let pp = pending.clone();
+
+            let subscribers: Subscribers = Rc::new(RefCell::new(HashMap::new()));
+            let ss = subscribers.clone();
+
+            let (closed_s, closed) = futures::channel::oneshot::channel();
+            let mut closed_s = Some(closed_s);
+
+            let error = Rc::new(RefCell::new(None));
+            let ee = error.clone();
+
+            let transferred = Rc::new(RefCell::new(Vec::new()));
+            let tt = transferred.clone();
+
+            let (ks, kr) = futures::channel::mpsc::unbounded();
+            let mut ks = Some(ks);
+            let _handle =
+                gloo::events::EventListener::new(&worker.borrow(), "message", move |event| {
+                    //log!("waaa");
+                    let event = event.dyn_ref::<web_sys::MessageEvent>().unwrap_throw();
+                    let data = event.data();
+
+                    //Ignore messages that weren't framed by the engine, e.g. from other libraries.
+                    let data: js_sys::Array = match data.dyn_into() {
+                        Ok(data) => data,
+                        Err(_) => return,
+                    };
+                    if data.length() < 2 {
+                        return;
+                    }
+                    let m = data.get(0);
+                    let k = data.get(1);
+
+                    if !m.is_null() {
+                        if let Some(s) = m.as_string() {
+                            if s == "ready" {
+                                if let Some(f) = fs.take() {
+                                    let caps: Result<Option<WorkerCapabilities>, _> =
+                                        if k.is_null() {
+                                            Ok(None)
+                                        } else {
+                                            serde_wasm_bindgen::from_value(k).map(Some).map_err(|e| {
+                                                EngineError::Serialization(e.to_string())
+                                            })
+                                        };
+                                    let _ = f.send(caps);
+                                }
+                            } else if s == "initialized" {
+                                ii.borrow_mut().fire();
+                            } else if s == "first_frame" {
+                                ff.borrow_mut().fire();
+                            } else if s == "error" {
+                                let message = k.as_string().unwrap_or_default();
+                                *ee.borrow_mut() = Some(message.clone());
+
+                                //The worker is dead, so fail everything that waits on it.
+                                let e = EngineError::WorkerPanicked(message);
+                                if let Some(f) = fs.take() {
+                                    let _ = f.send(Err(e.clone()));
+                                }
+                                ii.borrow_mut().fail(e.clone());
+                                ff.borrow_mut().fail(e.clone());
+                                for (_, s) in pp.borrow_mut().drain() {
+                                    let _ = s.send(Err(e.clone()));
+                                }
+                                for c in caps.borrow_mut().drain(..) {
+                                    let _ = c.send(Err(e.clone()));
+                                }
+
+                                //Also end the receiver's stream
+                                //and don't let shutdown wait for an acknowledgement.
+                                ks = None;
+                                if let Some(c) = closed_s.take() {
+                                    let _ = c.send(());
+                                }
+                            } else if s == "checkpoint" {
+                                let Some(name) = k.as_string() else { return };
+                                cc.borrow_mut().reach(name);
+                            } else if s == "close" {
+                                for (_, s) in pp.borrow_mut().drain() {
+                                    let _ = s.send(Err(EngineError::Closed));
+                                }
+                                for c in caps.borrow_mut().drain(..) {
+                                    let _ = c.send(Err(EngineError::Closed));
+                                }
+                                if let Some(c) = closed_s.take() {
+                                    let _ = c.send(());
+                                }
+                            } else if s == "capture" {
+                                let Ok(blob) = k.dyn_into::<web_sys::Blob>() else { return };
+                                for c in caps.borrow_mut().drain(..) {
+                                    let _ = c.send(Ok(blob.clone()));
+                                }
+                            } else if s == "tagged" {
+                                let Ok(k) = k.dyn_into::<js_sys::Array>() else { return };
+                                let Some(tag) = k.get(0).as_string() else { return };
+                                if let Some(subs) = ss.borrow_mut().get_mut(&tag) {
+                                    let value = k.get(1);
+                                    subs.retain_mut(|f| f(&value));
+                                }
+                            }
+                        } else if let Some(id) = m.as_f64() {
+                            if let Some(s) = pp.borrow_mut().remove(&(id as u64)) {
+                                //Resolve the request either way instead of leaving it pending.
+                                let a = serde_wasm_bindgen::from_value(k)
+                                    .map_err(|e| EngineError::Serialization(e.to_string()));
+                                let _ = s.send(a);
+                            }
+                        }
+                    } else {
+                        let Ok(a) = serde_wasm_bindgen::from_value(k) else { return };
+                        if data.length() > 2 {
+                            //Kept with their message so the two can't get separated.
+                            let Ok(buffers) = data.get(2).dyn_into::<js_sys::Array>() else { return };
+                            let buffers = buffers.iter().map(|b| b.unchecked_into()).collect();
+                            tt.borrow_mut().push((a, buffers));
+                        } else if let Some(ks) = &ks {
+                            ks.unbounded_send(a).unwrap_throw();
+                        }
+                    }
+                });
+
+            let capabilities = match main_options.ready_timeout {
+                Some(ms) => match futures::future::select(fr, TimeoutFuture::new(ms)).await {
+                    futures::future::Either::Left((a, _)) => a.unwrap_throw(),
+                    futures::future::Either::Right(_) => Err(EngineError::ReadyTimeout),
+                },
+                None => fr.await.unwrap_throw(),
+            };
+            let capabilities = match capabilities {
+                Ok(capabilities) => capabilities,
+                Err(e) => {
+                    worker.borrow().terminate();
+                    return Err(e);
+                }
+            };
+
+            let arr: js_sys::Array = canvases.iter().collect();
+
+            //The first canvas is the control, any others go in the payload.
+            let rest: js_sys::Array = canvases.iter().skip(1).collect();
+            let data = frame(canvases[0].clone().into(), rest.into());
+
+            worker
+                .borrow()
+                .post_message_with_transfer(&data, &arr)
+                .map_err(EngineError::Transfer)?;
+
+            Ok((
+                EngineMain {
+                    worker: Outbox {
+                        worker,
+                        batch: Rc::new(RefCell::new(Batch::default())),
+                    },
+                    _handle,
+                    capabilities,
+                    checkpoints,
+                    initialized,
+                    first_frame,
+                    error,
+                    transferred,
+                    captures,
+                    pending,
+                    subscribers,
+                    next_id: 0,
+                    closed,
+                    events: Vec::new(),
+                    next_event: 0,
+                    _p: PhantomData,
+                },
+                kr,
+            ))
+        }
+
+        ///
+        /// The capabilities the worker reported along with its "ready" message, if any.
+        /// See [`EngineWorker::new_with_capabilities`].
+        ///
+        pub fn capabilities(&self) -> Option<&WorkerCapabilities> {
+            self.capabilities.as_ref()
+        }
+
+        ///
+        /// Resolves once the worker has called [`EngineWorker::signal_checkpoint`] with the same name.
+        /// Resolves immediately if the checkpoint was already reached.
+        ///
+        pub fn wait_checkpoint(&self, name: &str) -> impl std::future::Future<Output = ()> {
+            let r = self.checkpoints.borrow_mut().wait(name);
+            async move {
+                if let Some(r) = r {
+                    let _ = r.await;
+                }
+            }
+        }
+
+        ///
+        /// Resolves once the worker has called [`EngineWorker::signal_initialized`],
+        /// i.e. once it is actually able to render. Resolves immediately if that already happened.
+        /// Fails if the worker panics first.
+        ///
+        pub fn wait_initialized(
+            &self,
+        ) -> impl std::future::Future<Output = Result<(), EngineError>> {
+            self.initialized.borrow_mut().wait()
+        }
+
+        ///
+        /// The panic message of the worker, if it panicked. When that happens the receiver
+        /// returned by [`EngineMain::new`] ends.
+        ///
+        pub fn worker_error(&self) -> Option<String> {
+            self.error.borrow().clone()
+        }
+
+        //The error to fail new requests with once the worker is dead.
+        fn dead(&self) -> Option<EngineError> {
+            self.error.borrow().clone().map(EngineError::WorkerPanicked)
+        }
+
+        ///
+        /// Resolves once the worker has called [`EngineWorker::signal_frame_drawn`] for the first time,
+        /// i.e. once something is on screen. Resolves immediately if that already happened.
+        /// Fails if the worker panics first.
+        ///
+        pub fn wait_first_frame(
+            &self,
+        ) -> impl std::future::Future<Output = Result<(), EngineError>> {
+            self.first_frame.borrow_mut().wait()
+        }
+
+        ///
+        /// Request a png capture of the canvas. The worker fulfills it after the next frame
+        /// it draws, in [`EngineWorker::signal_frame_drawn`], so the result arrives a frame later.
+        /// Fails if the worker panics or shuts down first.
+        ///
+        pub fn capture_frame(
+            &mut self,
+        ) -> impl std::future::Future<Output = Result<web_sys::Blob, EngineError>> {
+            let (s, r) = futures::channel::oneshot::channel();
+            match self.dead() {
+                Some(e) => {
+                    let _ = s.send(Err(e));
+                }
+                None => {
+                    self.captures.borrow_mut().push(s);
+                    self.worker.post(&frame(JsValue::from_str("capture"), JsValue::null()));
+                }
+            }
+
+            async move { r.await.unwrap_throw() }
+        }
+
+        ///
+        /// Send a message to the worker and wait for it to respond through [`EngineWorker::reply`].
+        /// The worker receives it through [`EngineWorker::take_requests`] instead of its normal stream.
+        /// Fails if the worker panics or shuts down before responding.
+        ///
+        pub fn request(&mut self, val: MW) -> Reply<WM> {
+            let id = self.next_id;
+            self.next_id += 1;
+
+            let (s, recv) = futures::channel::oneshot::channel();
+            match self.dead() {
+                Some(e) => {
+                    let _ = s.send(Err(e));
+                }
+                None => {
+                    self.pending.borrow_mut().insert(id, s);
+                    let a = serde_wasm_bindgen::to_value(&val).unwrap_throw();
+                    self.worker.post(&frame(JsValue::from_f64(id as f64), a));
+                }
+            }
+
+            Reply {
+                id,
+                pending: self.pending.clone(),
+                recv,
+            }
+        }
+
+        ///
+        /// Ask the worker to stop. The worker's message stream ends so that its
+        /// [`FrameTimer::next`] returns `None`. Resolves once the worker acknowledges by
+        /// dropping its [`EngineWorker`], after which the worker is terminated.
+        /// Resolves right away if the worker has already reported an error, see
+        /// [`EngineMain::worker_error`].
+        ///
+        pub async fn shutdown(mut self) {
+            self.shutdown_in_place().await;
+        }
+
+        //Same as shutdown, for owners that can't give up the engine, like EngineMainPool.
+        pub(crate) async fn shutdown_in_place(&mut self) {
+            self.worker.post(&frame(JsValue::from_str("shutdown"), JsValue::null()));
+
+            let _ = (&mut self.closed).await;
+            self.worker.terminate();
+        }
+
+        //Terminate the worker right away, without waiting for it to acknowledge.
+        pub(crate) fn terminate(&self) {
+            self.worker.terminate();
+        }
+
+        ///
+        /// Pause the worker. See [`EngineWorker::pause_handle`].
+        ///
+        pub fn pause(&mut self) {
+            self.worker.post(&frame(JsValue::from_str("pause"), JsValue::null()));
+        }
+
+        pub fn resume(&mut self) {
+            self.worker.post(&frame(JsValue::from_str("resume"), JsValue::null()));
+        }
+
+        ///
+        /// Pause the worker whenever the page is hidden and resume it once it is visible again.
+        /// The forwarding stops when the returned listener is dropped.
+        ///
+        pub fn forward_visibility(&self) -> gloo::events::EventListener {
+            let w = self.worker.clone();
+            let document = gloo::utils::document();
+            gloo::events::EventListener::new(&document.clone(), "visibilitychange", move |_| {
+                let control = if document.hidden() { "pause" } else { "resume" };
+                w.post(&frame(JsValue::from_str(control), JsValue::null()));
+            })
+        }
+
+        ///
+        /// Tell the worker the canvas should be resized. Since the canvas was transferred,
+        /// the worker applies the new size through [`EngineWorker::poll_resize`].
+        ///
+        pub fn resize(&mut self, width: u32, height: u32) {
+            let dim = js_sys::Array::of2(&width.into(), &height.into());
+
+            self.worker.post(&frame(JsValue::from_str("resize"), dim.into()));
+        }
+
+        ///
+        /// Same as [`EngineMain::post_message`] except the buffers are transferred to the worker
+        /// instead of copied. The message doesn't go through the worker's message stream, the
+        /// worker gets it together with the buffers through [`EngineWorker::take_transferred`].
+        ///
+        /// Transferred buffers are detached on the main thread and can no longer be used here.
+        ///
+        pub fn post_message_with_transfer(&mut self, val: MW, buffers: &[js_sys::ArrayBuffer]) {
+            let a = serde_wasm_bindgen::to_value(&val).unwrap_throw();
+
+            let transfer: js_sys::Array = buffers.iter().collect();
+
+            let data = frame(JsValue::null(), a);
+            data.set(2, transfer.clone().into());
+
+            self.worker.post_with_transfer(&data, &transfer);
+        }
+
+        pub fn post_message(&mut self, val: MW) {
+            let a = serde_wasm_bindgen::to_value(&val).unwrap_throw();
+            self.post_message_value(a);
+        }
+
+        ///
+        /// Take the messages sent through [`EngineWorker::post_message_with_transfer`]
+        /// that arrived so far, each with its buffers, in the order they were sent.
+        ///
+        pub fn take_transferred(&mut self) -> Vec<(WM, Vec<js_sys::ArrayBuffer>)> {
+            std::mem::take(&mut self.transferred.borrow_mut())
+        }
+
+        //Post a payload that was already converted.
+        pub(crate) fn post_message_value(&mut self, a: JsValue) {
+            self.worker.post(&frame(JsValue::null(), a));
+        }
+
+        ///
+        /// Register a new event that will be packaged and sent to the worker thread.
+        ///
+        pub fn register_event(
+            &mut self,
+            elem: &web_sys::HtmlElement,
+            event_type: &'static str,
+            mut func: impl FnMut(EventData) -> MW + 'static,
+        ) -> gloo::events::EventListener {
+            self.register_event_with(elem, event_type, false, move |e| Some(func(e)))
+        }
+
+        ///
+        /// Same as [`EngineMain::register_event`] but for keyboard events like `keydown`.
+        /// Makes `elem` focusable first, since otherwise it never receives them.
+        ///
+        pub fn register_keyboard_event(
+            &mut self,
+            elem: &web_sys::HtmlElement,
+            event_type: &'static str,
+            func: impl FnMut(EventData) -> MW + 'static,
+        ) -> gloo::events::EventListener {
+            utils::make_focusable(elem);
+            self.register_event(elem, event_type, func)
+        }
+
+        ///
+        /// Same as [`EngineMain::register_event`] except nothing is sent to the worker
+        /// when `func` returns `None`.
+        ///
+        pub fn register_event_filtered(
+            &mut self,
+            elem: &web_sys::HtmlElement,
+            event_type: &'static str,
+            func: impl FnMut(EventData) -> Option<MW> + 'static,
+        ) -> gloo::events::EventListener {
+            self.register_event_with(elem, event_type, false, func)
+        }
+
+        ///
+        /// Same as [`EngineMain::register_event`] but the listener is registered as passive,
+        /// so the browser does not wait on it before scrolling. Use this for `touchmove` and
+        /// `wheel`. The listener cannot call `prevent_default`.
+        ///
+        pub fn register_event_passive(
+            &mut self,
+            elem: &web_sys::HtmlElement,
+            event_type: &'static str,
+            mut func: impl FnMut(EventData) -> MW + 'static,
+        ) -> gloo::events::EventListener {
+            self.register_event_with(elem, event_type, true, move |e| Some(func(e)))
+        }
+
+        fn register_event_with(
+            &mut self,
+            elem: &web_sys::HtmlElement,
+            event_type: &'static str,
+            passive: bool,
+            mut func: impl FnMut(EventData) -> Option<MW> + 'static,
+        ) -> gloo::events::EventListener {
+            let w = self.worker.clone();
+
+            let e = elem.clone();
+
+
+            use gloo::events::EventListenerOptions;
+            use gloo::events::EventListenerPhase;
+            let options=EventListenerOptions {
+                phase: EventListenerPhase::Bubble,
+                passive,
+            };
+
+            gloo::events::EventListener::new_with_options(elem, event_type,options, move |event| {
+                let e = EventData {
+                    elem: &e,
+                    event,
+                    event_type,
+                };
+
+                let val = match func(e) {
+                    Some(val) => val,
+                    None => return,
+                };
+                let a = serde_wasm_bindgen::to_value(&val).unwrap_throw();
+
+                w.post(&frame(JsValue::null(), a));
+            })
+        }
+
+        ///
+        /// Receive the values the worker sends with [`EngineWorker::post_tagged`] under `tag`.
+        /// Values sent before subscribing, or under a tag nobody subscribed to, are discarded.
+        ///
+        pub fn subscribe<S: for<'a> Deserialize<'a> + 'static>(
+            &mut self,
+            tag: &str,
+        ) -> futures::channel::mpsc::UnboundedReceiver<S> {
+            let (s, r) = futures::channel::mpsc::unbounded();
+            self.subscribers
+                .borrow_mut()
+                .entry(tag.to_string())
+                .or_default()
+                .push(Box::new(move |value: &JsValue| {
+                    s.unbounded_send(serde_wasm_bindgen::from_value(value.clone()).unwrap_throw()).is_ok()
+                }));
+            r
+        }
+
+        ///
+        /// Same as [`EngineMain::register_event`] except the values are queued and posted to the
+        /// worker together once per animation frame, instead of one message per event.
+        /// Useful for high frequency events like `mousemove`. The worker receives them
+        /// one by one in order, same as with [`EngineMain::register_event`]. Anything else
+        /// posted to the worker first flushes the queued values, so they are never overtaken.
+        ///
+        pub fn register_event_batched(
+            &mut self,
+            elem: &web_sys::HtmlElement,
+            event_type: &'static str,
+            mut func: impl FnMut(EventData) -> MW + 'static,
+        ) -> gloo::events::EventListener {
+            let w = self.worker.clone();
+            let e = elem.clone();
+
+            let options = gloo::events::EventListenerOptions {
+                phase: gloo::events::EventListenerPhase::Bubble,
+                passive: false,
+            };
+
+            gloo::events::EventListener::new_with_options(elem, event_type, options, move |event| {
+                let e = EventData {
+                    elem: &e,
+                    event,
+                    event_type,
+                };
+
+                let val = func(e);
+                let a = serde_wasm_bindgen::to_value(&val).unwrap_throw();
+
+                let mut b = w.batch.borrow_mut();
+                b.queue.push(a);
+                if b.scheduled {
+                    return;
+                }
+                b.scheduled = true;
+
+                let worker = w.worker.clone();
+                //Weak since the batch owns the request, which owns this callback.
+                let bb = Rc::downgrade(&w.batch);
+                b.frame = Some(gloo::render::request_animation_frame(move |_| {
+                    let bb = match bb.upgrade() {
+                        Some(bb) => bb,
+                        None => return,
+                    };
+                    let mut b = bb.borrow_mut();
+                    b.scheduled = false;
+                    //Empty if something else was posted since, which flushed it already.
+                    flush_batch(&worker.borrow(), &mut b);
+                }));
+            })
+        }
+
+        ///
+        /// Same as [`EngineMain::register_event`] but the engine keeps the listener alive
+        /// until [`EngineMain::unregister_event`] is called or the engine is dropped.
+        ///
+        pub fn register_event_owned(
+            &mut self,
+            elem: &web_sys::HtmlElement,
+            event_type: &'static str,
+            func: impl FnMut(EventData) -> MW + 'static,
+        ) -> EventHandle {
+            let listener = self.register_event(elem, event_type, func);
+            let handle = EventHandle(self.next_event);
+            self.next_event += 1;
+            self.events.push((handle, listener));
+            handle
+        }
+
+        ///
+        /// Remove a listener registered with [`EngineMain::register_event_owned`].
+        /// Returns false if the handle was already removed.
+        ///
+        pub fn unregister_event(&mut self, handle: EventHandle) -> bool {
+            let len = self.events.len();
+            self.events.retain(|(h, _)| *h != handle);
+            self.events.len() != len
+        }
+    }
+}
+
+///
+/// Data that can be accessed when handling events in the main thread to help
+/// construct the data to be passed to the worker thread.
+///
+pub struct EventData<'a> {
+    pub elem: &'a web_sys::HtmlElement,
+    pub event: &'a web_sys::Event,
+    pub event_type: &'static str,
+}
+
+impl<'a> EventData<'a> {
+    ///
+    /// For mouse events, the position of the cursor relative to the top left of `elem`.
+    ///
+    pub fn mouse_pos(&self) -> Option<(f64, f64)> {
+        let e = self.event.dyn_ref::<web_sys::MouseEvent>()?;
+        let rect = self.elem.get_bounding_client_rect();
+        Some((
+            e.client_x() as f64 - rect.left(),
+            e.client_y() as f64 - rect.top(),
+        ))
+    }
+
+    ///
+    /// For keyboard events, the value of the key that was pressed.
+    ///
+    pub fn key(&self) -> Option<String> {
+        self.event
+            .dyn_ref::<web_sys::KeyboardEvent>()
+            .map(|e| e.key())
+    }
+}
+
+///
+/// GL capabilities detected on the worker thread. Can be sent to the main thread
+/// along with the "ready" message so that it can adjust before the first frame.
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorkerCapabilities {
+    pub extensions: Vec<String>,
+    pub max_texture_size: u32,
+}
+
+impl WorkerCapabilities {
+    ///
+    /// Detect the capabilities using a throwaway offscreen canvas.
+    /// Returns `None` if a webgl2 context could not be created.
+    ///
+    pub fn detect() -> Option<WorkerCapabilities> {
+        let canvas = web_sys::OffscreenCanvas::new(1, 1).ok()?;
+        let ctx: web_sys::WebGl2RenderingContext =
+            canvas.get_context("webgl2").ok()??.dyn_into().ok()?;
+
+        let extensions = ctx
+            .get_supported_extensions()
+            .map(|a| a.iter().filter_map(|e| e.as_string()).collect())
+            .unwrap_or_default();
+
+        let max_texture_size = ctx
+            .get_parameter(web_sys::WebGl2RenderingContext::MAX_TEXTURE_SIZE)
+            .ok()?
+            .as_f64()? as u32;
+
+        Some(WorkerCapabilities {
+            extensions,
+            max_texture_size,
+        })
+    }
+}
+
+pub use pool::EngineMainPool;
+mod pool {
+    use super::*;
+
+    ///
+    /// An [`EngineMain`] plus extra compute-only workers spawned from the same script.
+    /// The compute workers don't get a canvas, only their index, which they can read
+    /// through [`EngineWorker::worker_index`]. The worker that owns the canvas has index 0,
+    /// the compute workers are numbered from 1.
+    ///
+    pub struct EngineMainPool<MW, WM> {
+        main: EngineMain<MW, WM>,
+        compute: Vec<Compute>,
+        next: usize,
+    }
+
+    struct Compute {
+        worker: web_sys::Worker,
+        _handle: gloo::events::EventListener,
+        error: Rc<RefCell<Option<String>>>,
+        //Fires once the worker acknowledged a shutdown, or panicked.
+        closed: futures::channel::oneshot::Receiver<()>,
+    }
+
+    impl<MW: 'static + Serialize, WM: for<'a> Deserialize<'a> + 'static> EngineMainPool<MW, WM> {
+        ///
+        /// Create the engine and `num_compute` compute workers. Blocks until all of them are ready.
+        /// The first receiver gets the messages of the canvas worker, the second
+        /// the messages of the compute workers along with their index.
+        ///
+        pub async fn new(
+            web_worker_url: &str,
+            canvas: web_sys::OffscreenCanvas,
+            num_compute: usize,
+        ) -> Result<
+            (
+                Self,
+                futures::channel::mpsc::UnboundedReceiver<WM>,
+                futures::channel::mpsc::UnboundedReceiver<(usize, WM)>,
+            ),
+            EngineError,
+        > {
+            Self::new_with_options(web_worker_url, canvas, num_compute, MainOptions::default())
+                .await
+        }
+
+        ///
+        /// Same as [`EngineMainPool::new`] but with options that apply to every worker.
+        /// The ready timeout covers waiting for all of the compute workers together.
+        /// If anything fails, the workers that were already created are terminated.
+        ///
+        pub async fn new_with_options(
+            web_worker_url: &str,
+            canvas: web_sys::OffscreenCanvas,
+            num_compute: usize,
+            main_options: MainOptions,
+        ) -> Result<
+            (
+                Self,
+                futures::channel::mpsc::UnboundedReceiver<WM>,
+                futures::channel::mpsc::UnboundedReceiver<(usize, WM)>,
+            ),
+            EngineError,
+        > {
+            let worker_type = main_options.worker_type;
+            let ready_timeout = main_options.ready_timeout;
+
+            let (main, kr) =
+                EngineMain::new_with_options(web_worker_url, canvas, main_options).await?;
+
+            let (cs, cr) = futures::channel::mpsc::unbounded();
+
+            //On an early return, dropping the pool terminates the compute workers
+            //created so far. The canvas worker has to be terminated by hand.
+            let mut pool = EngineMainPool {
+                main,
+                compute: Vec::with_capacity(num_compute),
+                next: 0,
+            };
+
+            let mut readies = Vec::with_capacity(num_compute);
+            for index in 1..=num_compute {
+                let options = web_sys::WorkerOptions::new();
+                options.set_type(worker_type);
+                let worker = match web_sys::Worker::new_with_options(web_worker_url, &options) {
+                    Ok(worker) => worker,
+                    Err(e) => {
+                        pool.main.terminate();
+                        return Err(EngineError::WorkerCreation(e));
+                    }
+                };
+
+                let (fs, fr) = futures::channel::oneshot::channel();
+                let mut fs = Some(fs);
+                let cs = cs.clone();
+
+                let error = Rc::new(RefCell::new(None));
+                let ee = error.clone();
+
+                let (closed_s, closed) = futures::channel::oneshot::channel();
+                let mut closed_s = Some(closed_s);
+
+                let handle = gloo::events::EventListener::new(&worker, "message", move |event| {
+                    let event = event.dyn_ref::<web_sys::MessageEvent>().unwrap_throw();
+
+                    //Ignore messages that weren't framed by the engine, e.g. from other libraries.
+                    let data: js_sys::Array = match event.data().dyn_into() {
+                        Ok(data) => data,
+                        Err(_) => return,
+                    };
+                    if data.length() < 2 {
+                        return;
+                    }
+                    let m = data.get(0);
+                    let k = data.get(1);
+
+                    if m.is_null() {
+                        let Ok(a) = serde_wasm_bindgen::from_value(k) else { return };
+                        let _ = cs.unbounded_send((index, a));
+                        return;
+                    }
+                    match m.as_string().as_deref() {
+                        Some("ready") => {
+                            if let Some(fs) = fs.take() {
+                                let _ = fs.send(Ok(()));
+                            }
+                        }
+                        Some("error") => {
+                            let message = k.as_string().unwrap_or_default();
+                            *ee.borrow_mut() = Some(message.clone());
+                            if let Some(fs) = fs.take() {
+                                let _ = fs.send(Err(EngineError::WorkerPanicked(message)));
+                            }
+                            if let Some(c) = closed_s.take() {
+                                let _ = c.send(());
+                            }
+                        }
+                        Some("close") => {
+                            if let Some(c) = closed_s.take() {
+                                let _ = c.send(());
+                            }
+                        }
+                        _ => {}
+                    }
+                });
+
+                pool.compute.push(Compute {
+                    worker,
+                    _handle: handle,
+                    error,
+                    closed,
+                });
+                readies.push(fr);
+            }
+
+            let readies = futures::future::join_all(readies);
+            let readies = match ready_timeout {
+                Some(ms) => match futures::future::select(readies, TimeoutFuture::new(ms)).await {
+                    futures::future::Either::Left((readies, _)) => readies,
+                    futures::future::Either::Right(_) => {
+                        pool.main.terminate();
+                        return Err(EngineError::ReadyTimeout);
+                    }
+                },
+                None => readies.await,
+            };
+            for ready in readies {
+                if let Err(e) = ready.unwrap_throw() {
+                    pool.main.terminate();
+                    return Err(e);
+                }
+            }
+
+            for (index, Compute { worker, .. }) in pool.compute.iter().enumerate() {
+                worker
+                    .post_message(&frame(
+                        JsValue::from_str("index"),
+                        JsValue::from_f64((index + 1) as f64),
+                    ))
+                    .unwrap_throw();
+            }
+
+            Ok((pool, kr, cr))
+        }
 
         ///
-        /// Register a new event that will be packaged and sent to the worker thread.
+        /// The engine of the worker that owns the canvas.
+        ///
+        pub fn main(&mut self) -> &mut EngineMain<MW, WM> {
+            &mut self.main
+        }
+
+        ///
+        /// The number of workers, including the one that owns the canvas.
+        ///
+        pub fn num_workers(&self) -> usize {
+            self.compute.len() + 1
+        }
+
+        ///
+        /// Ask every worker to stop, see [`EngineMain::shutdown`]. Resolves once all of them
+        /// acknowledged by dropping their [`EngineWorker`], or panicked,
+        /// after which they are terminated.
+        ///
+        pub async fn shutdown(mut self) {
+            for c in self.compute.iter() {
+                c.worker
+                    .post_message(&frame(JsValue::from_str("shutdown"), JsValue::null()))
+                    .unwrap_throw();
+            }
+            let closed = futures::future::join_all(self.compute.iter_mut().map(|c| &mut c.closed));
+            futures::future::join(self.main.shutdown_in_place(), closed).await;
+            //Dropping the pool terminates the compute workers.
+        }
+        ///
+        /// The panic message of the worker with the given index, if it panicked.
+        /// 0 is the worker that owns the canvas, see [`EngineMain::worker_error`].
+        ///
+        pub fn worker_error(&self, index: usize) -> Option<String> {
+
+            if index == 0 {
+                return self.main.worker_error();
+            }
+            self.compute[index - 1].error.borrow().clone()
+        }
+
+        ///
+        /// Send a message to the worker with the given index. 0 is the worker that owns the canvas.
+        ///
+        pub fn post_message_to(&mut self, index: usize, val: MW) {
+            if index == 0 {
+                self.main.post_message(val);
+                return;
+            }
+            let a = serde_wasm_bindgen::to_value(&val).unwrap_throw();
+            self.compute[index - 1]
+                .worker
+                .post_message(&frame(JsValue::null(), a))
+                .unwrap_throw();
+        }
+
+        ///
+        /// Send a message to the next compute worker, cycling through them.
+        /// Goes to the worker that owns the canvas if there are no compute workers.
+        ///
+        pub fn post_message_round_robin(&mut self, val: MW) {
+            if self.compute.is_empty() {
+                self.main.post_message(val);
+                return;
+            }
+            let index = self.next % self.compute.len();
+            self.next = self.next.wrapping_add(1);
+            self.post_message_to(index + 1, val);
+        }
+
+        ///
+        /// Send the same message to every worker, including the one that owns the canvas.
+        ///
+        pub fn broadcast(&mut self, val: &MW) {
+            let a = serde_wasm_bindgen::to_value(val).unwrap_throw();
+            for c in self.compute.iter() {
+                c.worker
+                    .post_message(&frame(JsValue::null(), a.clone()))
+                    .unwrap_throw();
+            }
+            self.main.post_message_value(a);
+        }
+    }
+
+    impl<MW, WM> Drop for EngineMainPool<MW, WM> {
+        fn drop(&mut self) {
+            for c in self.compute.iter() {
+                c.worker.terminate();
+            }
+        }
+    }
+}
+
+pub use single::SingleThreadedEngine;
+mod single {
+    use super::*;
+
+    ///
+    /// A fallback for browsers that can't transfer a canvas to a worker,
+    /// see [`utils::supports_offscreen`]. Events are queued on the main thread
+    /// and the same render loop, e.g. built on [`FrameTimer`], runs there too
+    /// against a regular canvas element.
+    ///
+    pub struct SingleThreadedEngine<MW> {
+        canvas: web_sys::HtmlCanvasElement,
+        sender: futures::channel::mpsc::UnboundedSender<MW>,
+    }
+
+    impl<MW: 'static> SingleThreadedEngine<MW> {
+        ///
+        /// Create the engine. The receiver plays the role of the one returned by [`EngineWorker::new`].
+        ///
+        pub fn new(
+            canvas: web_sys::HtmlCanvasElement,
+        ) -> (Self, futures::channel::mpsc::UnboundedReceiver<MW>) {
+            let (sender, r) = futures::channel::mpsc::unbounded();
+            (SingleThreadedEngine { canvas, sender }, r)
+        }
+
+        pub fn canvas(&self) -> web_sys::HtmlCanvasElement {
+            self.canvas.clone()
+        }
+
+        pub fn post_message(&mut self, val: MW) {
+            //The receiver being dropped means the render loop has ended.
+            let _ = self.sender.unbounded_send(val);
+        }
+
+        ///
+        /// Same as [`EngineMain::register_event`] except the value is queued directly
+        /// instead of being posted to a worker.
         ///
         pub fn register_event(
             &mut self,
@@ -238,49 +2072,28 @@ mod main {
             event_type: &'static str,
             mut func: impl FnMut(EventData) -> MW + 'static,
         ) -> gloo::events::EventListener {
-            let w = self.worker.clone();
-
+            let sender = self.sender.clone();
             let e = elem.clone();
 
-
-            use gloo::events::EventListenerOptions;
-            use gloo::events::EventListenerPhase;
-            let options=EventListenerOptions {
-                phase: EventListenerPhase::Bubble,
+            let options = gloo::events::EventListenerOptions {
+                phase: gloo::events::EventListenerPhase::Bubble,
                 passive: false,
             };
 
-            gloo::events::EventListener::new_with_options(elem, event_type,options, move |event| {
+            gloo::events::EventListener::new_with_options(elem, event_type, options, move |event| {
                 let e = EventData {
                     elem: &e,
                     event,
                     event_type,
                 };
 
-                let val = func(e);
-                let a = JsValue::from_serde(&val).unwrap_throw();
-
-                let data = js_sys::Array::new();
-                data.set(0, JsValue::null());
-                data.set(1, a);
-
-                w.borrow().post_message(&data).unwrap_throw();
+                let _ = sender.unbounded_send(func(e));
             })
         }
     }
 }
 
-///
-/// Data that can be accessed when handling events in the main thread to help
-/// construct the data to be passed to the worker thread.
-///
-pub struct EventData<'a> {
-    pub elem: &'a web_sys::HtmlElement,
-    pub event: &'a web_sys::Event,
-    pub event_type: &'static str,
-}
-
-pub use worker::EngineWorker;
+pub use worker::{ContextEvent, EngineWorker};
 mod worker {
     use super::*;
     ///
@@ -288,16 +2101,72 @@ mod worker {
     ///
     pub struct EngineWorker<MW, WM> {
         _handle: gloo::events::EventListener,
-        canvas: web_sys::OffscreenCanvas,
+        canvases: Vec<web_sys::OffscreenCanvas>,
+        context: RefCell<Option<web_sys::WebGl2RenderingContext>>,
+        index: usize,
+        capture_requested: Rc<std::cell::Cell<bool>>,
+        first_frame_sent: bool,
+        requests: Rc<RefCell<Vec<(u64, MW)>>>,
+        resize: Rc<std::cell::Cell<Option<(u32, u32)>>>,
+        transferred: Transferred<MW>,
+        pause: Pause,
+        context_events: Rc<RefCell<std::collections::VecDeque<ContextEvent>>>,
+        _context_handles: Vec<gloo::events::EventListener>,
         _p: PhantomData<(MW, WM)>,
     }
 
+    ///
+    /// A change in the state of the canvas's webgl context. See [`EngineWorker::poll_context_event`].
+    ///
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum ContextEvent {
+        /// The context was lost. Drawing does nothing until it is restored.
+        Lost,
+        /// The context was restored. All programs and buffers have to be recreated.
+        Restored,
+    }
+
     impl<MW: 'static + for<'a> Deserialize<'a>, WM: Serialize> EngineWorker<MW, WM> {
         ///
-        /// Get the offscreen canvas.
+        /// Get the offscreen canvas. Same as `canvas_at(0)`.
+        /// Throws on the compute-only workers of an [`EngineMainPool`].
         ///
         pub fn canvas(&self) -> web_sys::OffscreenCanvas {
-            self.canvas.clone()
+            self.canvas_at(0)
+        }
+
+        ///
+        /// The webgl2 context of the canvas, created on first use with
+        /// [`utils::get_context_webgl2_offscreen`]. To use other [`utils::ContextAttributes`], create
+        /// the context with them before calling this, the browser then returns that same context.
+        ///
+        /// When mixing custom gl calls with [`simple2d`] drawing, restore any state,
+        /// e.g. blending or bound buffers, that the drawing code relies on.
+        ///
+        pub fn context(&self) -> web_sys::WebGl2RenderingContext {
+            self.context
+                .borrow_mut()
+                .get_or_insert_with(|| utils::get_context_webgl2_offscreen(&self.canvas()))
+                .clone()
+        }
+
+        ///
+        /// Get one of the canvases passed to [`EngineMain::new_multi`].
+        /// Throws if there is no canvas at that index.
+        ///
+        pub fn canvas_at(&self, index: usize) -> web_sys::OffscreenCanvas {
+            self.canvases
+                .get(index)
+                .cloned()
+                .expect_throw("no canvas at this index")
+        }
+
+        ///
+        /// The index of this worker in its [`EngineMainPool`].
+        /// The worker that owns the canvas, and any worker of a plain [`EngineMain`], is 0.
+        ///
+        pub fn worker_index(&self) -> usize {
+            self.index
         }
 
         ///
@@ -308,62 +2177,494 @@ mod worker {
         pub async fn new() -> (
             EngineWorker<MW, WM>,
             futures::channel::mpsc::UnboundedReceiver<MW>,
+        ) {
+            Self::new_inner(None).await
+        }
+
+        ///
+        /// Same as [`EngineWorker::new`] except the capabilities are sent along with
+        /// the "ready" message so that the main thread can read them via [`EngineMain::capabilities`].
+        ///
+        pub async fn new_with_capabilities(
+            capabilities: WorkerCapabilities,
+        ) -> (
+            EngineWorker<MW, WM>,
+            futures::channel::mpsc::UnboundedReceiver<MW>,
+        ) {
+            Self::new_inner(Some(capabilities)).await
+        }
+
+        async fn new_inner(
+            capabilities: Option<WorkerCapabilities>,
+        ) -> (
+            EngineWorker<MW, WM>,
+            futures::channel::mpsc::UnboundedReceiver<MW>,
         ) {
             let scope = utils::get_worker_global_context();
 
+            //Report panics to the main thread, see EngineMain::worker_error.
+            //Only installed once, so that later engines in the same worker don't report twice.
+            static PANIC_HOOK: std::sync::Once = std::sync::Once::new();
+            PANIC_HOOK.call_once(|| {
+                let prev = std::panic::take_hook();
+                std::panic::set_hook(Box::new(move |info| {
+                    let _ = utils::get_worker_global_context().post_message(&frame(
+                        JsValue::from_str("error"),
+                        JsValue::from_str(&info.to_string()),
+                    ));
+                    prev(info);
+                }));
+            });
+
             let (fs, fr) = futures::channel::oneshot::channel();
             let mut fs = Some(fs);
 
             let (bags, bagf) = futures::channel::mpsc::unbounded();
+            let mut bags = Some(bags);
+
+            let capture_requested = Rc::new(std::cell::Cell::new(false));
+            let cr = capture_requested.clone();
+
+            let requests = Rc::new(RefCell::new(Vec::new()));
+            let rr = requests.clone();
 
+            let resize = Rc::new(std::cell::Cell::new(None));
+            let rs = resize.clone();
+
+            let transferred = Rc::new(RefCell::new(Vec::new()));
+            let tt = transferred.clone();
+
+            let pause = Pause::default();
+            let pp = pause.clone();
 
             let _handle = gloo::events::EventListener::new(&scope, "message", move |event| {
                 let event = event.dyn_ref::<web_sys::MessageEvent>().unwrap_throw();
                 let data = event.data();
 
-                let data: js_sys::Array = data.dyn_into().unwrap_throw();
+                //Ignore messages that weren't framed by the engine, e.g. from other libraries.
+                let data: js_sys::Array = match data.dyn_into() {
+                    Ok(data) => data,
+                    Err(_) => return,
+                };
+                if data.length() < 2 {
+                    return;
+                }
                 let offscreen = data.get(0);
                 let payload = data.get(1);
 
-                if !offscreen.is_null() {
-                    let offscreen: web_sys::OffscreenCanvas = offscreen.dyn_into().unwrap_throw();
-                    if let Some(fs) = fs.take() {
-                        fs.send(offscreen).unwrap_throw();
+                if let Some(s) = offscreen.as_string() {
+                    if s == "capture" {
+                        cr.set(true);
+                    } else if s == "pause" {
+                        pp.pause();
+                    } else if s == "resume" {
+                        pp.resume();
+                    } else if s == "index" {
+                        //Sent instead of the canvas to compute-only workers.
+                        let Some(index) = payload.as_f64() else { return };
+                        if let Some(fs) = fs.take() {
+                            fs.send((vec![], index as usize)).unwrap_throw();
+                        }
+                    } else if s == "batch" {
+                        let Ok(arr) = payload.dyn_into::<js_sys::Array>() else { return };
+                        for a in arr.iter() {
+                            let Ok(e) = serde_wasm_bindgen::from_value(a) else { continue };
+                            if let Some(bags) = &bags {
+                                bags.unbounded_send(e).unwrap_throw();
+                            }
+                        }
+                    } else if s == "shutdown" {
+                        //Dropping the sender ends the stream.
+                        bags = None;
+                    } else if s == "resize" {
+                        let Ok(dim) = payload.dyn_into::<js_sys::Array>() else { return };
+                        let (Some(w), Some(h)) = (dim.get(0).as_f64(), dim.get(1).as_f64()) else {
+                            return;
+                        };
+                        rs.set(Some((w as u32, h as u32)));
+                    }
+                } else if let Some(id) = offscreen.as_f64() {
+                    let Ok(e) = serde_wasm_bindgen::from_value(payload) else { return };
+                    rr.borrow_mut().push((id as u64, e));
+                } else if !offscreen.is_null() {
+                    if let Ok(offscreen) = offscreen.dyn_into::<web_sys::OffscreenCanvas>() {
+                        let mut canvases = vec![offscreen];
+                        if let Ok(rest) = payload.dyn_into::<js_sys::Array>() {
+                            canvases.extend(rest.iter().map(|c| c.unchecked_into()));
+                        }
+                        if let Some(fs) = fs.take() {
+                            fs.send((canvases, 0)).unwrap_throw();
+                        }
+                    }
+                } else if !payload.is_null() {
+                    let Ok(e) = serde_wasm_bindgen::from_value(payload) else { return };
+                    if data.length() > 2 {
+                        //Kept with their message so the two can't get separated.
+                        let Ok(buffers) = data.get(2).dyn_into::<js_sys::Array>() else { return };
+                        let buffers = buffers.iter().map(|b| b.unchecked_into()).collect();
+                        tt.borrow_mut().push((e, buffers));
+                    } else if let Some(bags) = &bags {
+                        bags.unbounded_send(e).unwrap_throw();
                     }
-                }
-
-                if !payload.is_null() {
-                    let e = payload.into_serde().unwrap_throw();
-                    bags.unbounded_send(e).unwrap_throw();
                 }
             });
 
-            let data = js_sys::Array::new();
-            data.set(0, JsValue::from_str("ready"));
-            data.set(1, JsValue::null());
+            let data = frame(
+                JsValue::from_str("ready"),
+                match &capabilities {
+                    Some(c) => serde_wasm_bindgen::to_value(c).unwrap_throw(),
+                    None => JsValue::null(),
+                },
+            );
 
             scope.post_message(&data).unwrap_throw();
 
-            let canvas = fr.await.unwrap_throw();
+            let (canvases, index) = fr.await.unwrap_throw();
+
+            //So the first poll_resize reports the size the canvas was transferred with,
+            //unless a resize from the main thread already arrived.
+            if let Some(canvas) = canvases.first() {
+                if resize.get().is_none() {
+                    resize.set(Some((canvas.width(), canvas.height())));
+                }
+            }
+
+            let context_events = Rc::new(RefCell::new(std::collections::VecDeque::new()));
+            let mut context_handles = Vec::new();
+            if let Some(canvas) = canvases.first() {
+                let ce = context_events.clone();
+                context_handles.push(gloo::events::EventListener::new_with_options(
+                    canvas,
+                    "webglcontextlost",
+                    gloo::events::EventListenerOptions::enable_prevent_default(),
+                    move |event| {
+                        //Without this the context is never restored.
+                        event.prevent_default();
+                        ce.borrow_mut().push_back(ContextEvent::Lost);
+                    },
+                ));
+                let ce = context_events.clone();
+                context_handles.push(gloo::events::EventListener::new(
+                    canvas,
+                    "webglcontextrestored",
+                    move |_| {
+                        ce.borrow_mut().push_back(ContextEvent::Restored);
+                    },
+                ));
+            }
 
             (
                 EngineWorker {
                     _handle,
-                    canvas,
+                    canvases,
+                    context: RefCell::new(None),
+                    index,
+                    capture_requested,
+                    first_frame_sent: false,
+                    requests,
+                    resize,
+                    transferred,
+                    pause,
+                    context_events,
+                    _context_handles: context_handles,
                     _p: PhantomData,
                 },
                 bagf,
             )
         }
 
+        ///
+        /// Notify the main thread that setup, e.g. creating programs and buffers, is done
+        /// and frames can be drawn. See [`EngineMain::wait_initialized`].
+        ///
+        pub fn signal_initialized(&mut self) {
+            let scope = utils::get_worker_global_context();
+
+            scope
+                .post_message(&frame(JsValue::from_str("initialized"), JsValue::null()))
+                .unwrap_throw();
+        }
+
+        ///
+        /// Call after drawing every frame. The first call notifies the main thread that
+        /// something is on screen, see [`EngineMain::wait_first_frame`].
+        /// Every call also fulfills a pending [`EngineMain::capture_frame`].
+        ///
+        pub fn signal_frame_drawn(&mut self) {
+            self.fulfill_capture();
+
+            if std::mem::replace(&mut self.first_frame_sent, true) {
+                return;
+            }
+            let scope = utils::get_worker_global_context();
+
+            scope
+                .post_message(&frame(JsValue::from_str("first_frame"), JsValue::null()))
+                .unwrap_throw();
+        }
+
+        ///
+        /// Notify the main thread that the named checkpoint was reached.
+        /// See [`EngineMain::wait_checkpoint`].
+        ///
+        pub fn signal_checkpoint(&mut self, name: &str) {
+            let scope = utils::get_worker_global_context();
+
+            scope
+                .post_message(&frame(
+                    JsValue::from_str("checkpoint"),
+                    JsValue::from_str(name),
+                ))
+                .unwrap_throw();
+        }
+
+        ///
+        /// If the main thread requested a capture through [`EngineMain::capture_frame`],
+        /// the canvas is converted to a png blob and sent back. Otherwise does nothing.
+        /// Already done by [`EngineWorker::signal_frame_drawn`].
+        ///
+        pub fn fulfill_capture(&mut self) {
+            if !self.capture_requested.replace(false) {
+                return;
+            }
+
+            let canvas = match self.canvases.first() {
+                Some(canvas) => canvas,
+                None => return,
+            };
+
+            let promise = canvas.convert_to_blob().unwrap_throw();
+            wasm_bindgen_futures::spawn_local(async move {
+                let blob = wasm_bindgen_futures::JsFuture::from(promise)
+                    .await
+                    .unwrap_throw();
+
+                utils::get_worker_global_context()
+                    .post_message(&frame(JsValue::from_str("capture"), blob))
+                    .unwrap_throw();
+            });
+        }
+
+        ///
+        /// Returns the latest size sent through [`EngineMain::resize`] since the last call, if any.
+        /// Multiple resizes are coalesced into the latest one.
+        /// The (first) canvas is resized before returning, so the caller only needs to update the viewport.
+        /// The first call after startup always returns a size, the transferred canvas size if
+        /// no resize was sent yet, so the viewport can be set up before the first frame.
+        ///
+        pub fn poll_resize(&mut self) -> Option<(u32, u32)> {
+            let (w, h) = self.resize.take()?;
+            if let Some(canvas) = self.canvases.first() {
+                if canvas.width() != w {
+                    canvas.set_width(w);
+                }
+                if canvas.height() != h {
+                    canvas.set_height(h);
+                }
+            }
+            Some((w, h))
+        }
+
+        ///
+        /// Returns the oldest context loss or restore event that has not been polled yet.
+        /// On [`ContextEvent::Restored`] every [`simple2d::GlProgram`] and buffer has to be recreated.
+        ///
+        pub fn poll_context_event(&mut self) -> Option<ContextEvent> {
+            self.context_events.borrow_mut().pop_front()
+        }
+
+        ///
+        /// The pause flag controlled by [`EngineMain::pause`] and [`EngineMain::resume`].
+        /// Attach it to a [`FrameTimer`] with [`FrameTimer::set_pause`].
+        ///
+        pub fn pause_handle(&self) -> Pause {
+            self.pause.clone()
+        }
+
+        pub fn pause(&mut self) {
+            self.pause.pause();
+        }
+
+        pub fn resume(&mut self) {
+            self.pause.resume();
+        }
+
+        ///
+        /// Take the messages sent through [`EngineMain::post_message_with_transfer`]
+        /// that arrived so far, each with its buffers, in the order they were sent.
+        ///
+        pub fn take_transferred(&mut self) -> Vec<(MW, Vec<js_sys::ArrayBuffer>)> {
+            std::mem::take(&mut self.transferred.borrow_mut())
+        }
+
+        ///
+        /// Take the requests sent through [`EngineMain::request`] that arrived so far,
+        /// along with the ids to pass to [`EngineWorker::reply`].
+        ///
+        pub fn take_requests(&mut self) -> Vec<(u64, MW)> {
+            std::mem::take(&mut self.requests.borrow_mut())
+        }
+
+        ///
+        /// Respond to a request taken from [`EngineWorker::take_requests`].
+        ///
+        pub fn reply(&mut self, id: u64, a: WM) {
+            let scope = utils::get_worker_global_context();
+
+            scope
+                .post_message(&frame(
+                    JsValue::from_f64(id as f64),
+                    serde_wasm_bindgen::to_value(&a).unwrap_throw(),
+                ))
+                .unwrap_throw();
+        }
+
+        ///
+        /// Send a value on a separate stream identified by `tag`, instead of through the
+        /// main receiver. The main thread receives it through [`EngineMain::subscribe`].
+        ///
+        pub fn post_tagged<S: Serialize>(&mut self, tag: &str, a: &S) {
+            let scope = utils::get_worker_global_context();
+
+            let payload = js_sys::Array::new();
+            payload.set(0, JsValue::from_str(tag));
+            payload.set(1, serde_wasm_bindgen::to_value(a).unwrap_throw());
+
+            scope
+                .post_message(&frame(JsValue::from_str("tagged"), payload.into()))
+                .unwrap_throw();
+        }
+
+        ///
+        /// Same as [`EngineWorker::post_message`] except the buffers are transferred to the main
+        /// thread instead of copied. The message doesn't go through the main thread's message
+        /// stream, the main thread gets it together with the buffers through
+        /// [`EngineMain::take_transferred`].
+        ///
+        /// Transferred buffers are detached on the worker and can no longer be used here.
+        ///
+        pub fn post_message_with_transfer(&mut self, a: WM, buffers: &[js_sys::ArrayBuffer]) {
+            let scope = utils::get_worker_global_context();
+
+            let transfer: js_sys::Array = buffers.iter().collect();
+
+            let data = frame(JsValue::null(), serde_wasm_bindgen::to_value(&a).unwrap_throw());
+            data.set(2, transfer.clone().into());
+
+            scope
+                .post_message_with_transfer(&data, &transfer)
+                .unwrap_throw();
+        }
+
         pub fn post_message(&mut self, a: WM) {
             let scope = utils::get_worker_global_context();
 
-            let data = js_sys::Array::new();
-            data.set(0, JsValue::null());
-            data.set(1, JsValue::from_serde(&a).unwrap_throw());
+            scope
+                .post_message(&frame(
+                    JsValue::null(),
+                    serde_wasm_bindgen::to_value(&a).unwrap_throw(),
+                ))
+                .unwrap_throw();
+        }
+    }
+
+    impl<MW, WM> Drop for EngineWorker<MW, WM> {
+        fn drop(&mut self) {
+            let scope = utils::get_worker_global_context();
+            let _ = scope.post_message(&frame(JsValue::from_str("close"), JsValue::null()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn ready_items_are_returned_with_an_instant_timer() {
+        let (s, r) = futures::channel::mpsc::unbounded();
+        let mut timer = FrameTimer::new_with_source(60, r, ManualTimeSource::new());
 
-            scope.post_message(&data).unwrap_throw();
+        for i in 0..3 {
+            s.unbounded_send(i).unwrap();
+        }
+        assert_eq!(block_on(timer.next()), Some(&[0, 1, 2][..]));
+
+        s.unbounded_send(3).unwrap();
+        drop(s);
+        assert_eq!(block_on(timer.next()), Some(&[3][..]));
+        assert_eq!(block_on(timer.next()), None);
+    }
+    #[test]
+    fn waiting_while_paused_registers_once() {
+        let (s, r) = futures::channel::mpsc::unbounded();
+        let mut timer = FrameTimer::new_with_source(60, r, ManualTimeSource::new());
+        let pause = Pause::default();
+        timer.set_pause(pause.clone());
+        timer.set_max_queued(Some(2));
+        pause.pause();
+
+        for i in 0..5 {
+            s.unbounded_send(i).unwrap();
+        }
+        let mut next = Box::pin(timer.next());
+        assert!((&mut next).now_or_never().is_none());
+        assert_eq!(pause.inner.borrow().waiting.len(), 1);
+        pause.resume();
+        let events = block_on(next).unwrap().to_vec();
+
+        assert_eq!(events, [3, 4]);
+        assert_eq!(timer.dropped_event_count(), 3);
+    }
+    //A time source whose timeouts never finish, counting how many are still alive.
+    #[derive(Clone, Default)]
+    struct StuckTimeSource {
+        alive: Rc<std::cell::Cell<usize>>,
+    }
+
+    struct Alive(Rc<std::cell::Cell<usize>>);
+
+    impl Drop for Alive {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() - 1);
+        }
+    }
+
+    impl StuckTimeSource {
+        fn pending_timeouts(&self) -> usize {
+            self.alive.get()
+        }
+    }
+
+    impl TimeSource for StuckTimeSource {
+        fn now(&self) -> f64 {
+            0.0
+        }
+        fn sleep(&self, _: u32) -> futures::future::LocalBoxFuture<'static, ()> {
+            self.alive.set(self.alive.get() + 1);
+            let alive = Alive(self.alive.clone());
+            Box::pin(futures::future::pending().map(move |()| drop(alive)))
+        }
+    }
+
+    #[test]
+    fn rapid_pause_resume_keeps_one_pending_timeout() {
+        let (_s, r) = futures::channel::mpsc::unbounded::<()>();
+        let source = StuckTimeSource::default();
+        let mut timer = FrameTimer::new_with_source(60, r, source.clone());
+        let pause = Pause::default();
+        timer.set_pause(pause.clone());
+
+        for _ in 0..10 {
+            assert!(timer.next().now_or_never().is_none());
+            assert!(timer.next().now_or_never().is_none());
+            assert_eq!(source.pending_timeouts(), 1);
+
+            pause.pause();
+            assert!(timer.next().now_or_never().is_none());
+            assert_eq!(source.pending_timeouts(), 0);
+            pause.resume();
         }
     }
 }
@@ -0,0 +1,91 @@
+//!
+//! A small deterministic random number generator.
+//!
+//! Unlike `Math.random()` it can be seeded and its state can be serialized along with
+//! the rest of the world, so procedural content is reproducible across replays and save/load.
+//! A common setup is for the main thread to pick a seed and send it to the worker at startup.
+//!
+//! Uses the PCG32 (XSH RR) algorithm.
+//!
+//! The state is serialized as two `u32` halves, since `serde_wasm_bindgen` turns a `u64`
+//! into a JS number and can't represent values above 2^53 that way.
+//!
+use serde::{Deserialize, Serialize};
+
+const MULTIPLIER: u64 = 6364136223846793005;
+const INCREMENT: u64 = 1442695040888963407;
+
+///
+/// A seedable PCG32 random number generator.
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    #[serde(with = "halves")]
+    state: u64,
+}
+
+//Serialize a u64 as [high, low].
+mod halves {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(val: &u64, s: S) -> Result<S::Ok, S::Error> {
+        [(*val >> 32) as u32, *val as u32].serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+        let [high, low] = <[u32; 2]>::deserialize(d)?;
+        Ok((high as u64) << 32 | low as u64)
+    }
+}
+
+impl Rng {
+    ///
+    /// Any `u64` is a valid seed. A seed picked on the JS side and passed in as a number
+    /// should stay within `Number.MAX_SAFE_INTEGER` (2^53 - 1) so it isn't rounded on the way.
+    ///
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng { state: 0 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(INCREMENT);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    ///
+    /// A float in the range `[0,1)`.
+    ///
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    ///
+    /// A float in the range `[start,end)`.
+    ///
+    pub fn range_f32(&mut self, start: f32, end: f32) -> f32 {
+        start + (end - start) * self.next_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_seeds() {
+        let mut rng = Rng::new(42);
+        let vals: Vec<_> = (0..4).map(|_| rng.next_u32()).collect();
+        assert_eq!(vals, [0xc2f57bd6, 0x6b07c4a9, 0x72b7b29b, 0x44215383]);
+
+        let mut rng = Rng::new(u64::MAX);
+        let vals: Vec<_> = (0..4).map(|_| rng.next_u32()).collect();
+        assert_eq!(vals, [0xd9313036, 0xcd4b6992, 0x7b8ec69e, 0x999dd010]);
+    }
+}
@@ -0,0 +1,325 @@
+use super::shader::{attrib_location, compile_shader, link_program, uniform_location, Buffer};
+use web_sys::{WebGl2RenderingContext, WebGlProgram, WebGlUniformLocation};
+
+const CIRCLE_VERT_SHADER_STR: &str = r#"#version 300 es
+in vec2 position;
+in float radius;
+in vec4 color;
+uniform mat3 mmatrix;
+out vec4 v_color;
+void main() {
+    gl_PointSize = radius * 2.0;
+    v_color = color;
+    vec3 pp=vec3(position,1.0);
+    gl_Position = vec4(mmatrix*pp, 1.0);
+}
+"#;
+
+const CIRCLE_FRAG_SHADER_STR: &str = r#"#version 300 es
+precision mediump float;
+in vec4 v_color;
+out vec4 out_color;
+uniform bool round_dots;
+uniform vec4 tint;
+
+void main() {
+    if(!round_dots){
+        out_color = v_color * tint;
+        return;
+    }
+    //coord is between -0.5 and 0.5
+    vec2 coord = gl_PointCoord - vec2(0.5,0.5);
+    float dis = length(coord);
+    float edge = fwidth(dis);
+    float alpha = 1.0 - smoothstep(0.5 - edge, 0.5, dis);
+    if(alpha <= 0.0){
+        discard;
+    }
+    out_color = vec4(v_color.rgb, v_color.a * alpha) * tint;
+}
+"#;
+
+//Used by CircleProgram::new_instanced. Each circle is a quad made of the corners below,
+//offset in pixels from its center, so there is no point size limit.
+const QUAD_VERT_SHADER_STR: &str = r#"#version 300 es
+in vec2 corner;
+in vec2 position;
+in float radius;
+in vec4 color;
+uniform mat3 mmatrix;
+uniform vec2 viewport;
+out vec4 v_color;
+out vec2 v_coord;
+void main() {
+    v_color = color;
+    v_coord = corner * 0.5;
+    vec3 pp = mmatrix * vec3(position, 1.0);
+    gl_Position = vec4(pp.xy + corner * radius * 2.0 / viewport, pp.z, 1.0);
+}
+"#;
+
+const QUAD_FRAG_SHADER_STR: &str = r#"#version 300 es
+precision mediump float;
+in vec4 v_color;
+in vec2 v_coord;
+out vec4 out_color;
+uniform bool round_dots;
+uniform vec4 tint;
+
+void main() {
+    if(!round_dots){
+        out_color = v_color * tint;
+        return;
+    }
+    //v_coord is between -0.5 and 0.5
+    float dis = length(v_coord);
+    float edge = fwidth(dis);
+    float alpha = 1.0 - smoothstep(0.5 - edge, 0.5, dis);
+    if(alpha <= 0.0){
+        discard;
+    }
+    out_color = vec4(v_color.rgb, v_color.a * alpha) * tint;
+}
+"#;
+
+//Two triangles covering [-1,1].
+const CORNERS: [[f32; 2]; 6] = [
+    [-1.0, -1.0],
+    [1.0, -1.0],
+    [1.0, 1.0],
+    [-1.0, -1.0],
+    [1.0, 1.0],
+    [-1.0, 1.0],
+];
+
+///
+/// The shape of the dots drawn by [`CircleProgram::draw_dots`].
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DotShape {
+    Square,
+    /// An antialiased circle.
+    Round,
+}
+
+///
+/// Draws antialiased circles where every circle has its own radius and color.
+/// Radii are in pixels. Positions are transformed by the matrix passed to
+/// [`CircleProgram::draw`], so when drawing through [`super::View`] they are in game coordinates.
+/// Enable blending, e.g. with [`super::CtxWrap::setup_alpha`], for the smooth edges to show.
+///
+/// By default every circle is a `gl_Point`, whose size the gpu clamps to its max point size,
+/// as little as 64 pixels on some devices. [`CircleProgram::new_instanced`] draws every circle
+/// as an instanced quad instead, which has no size limit but shades six vertices per circle
+/// instead of one.
+///
+pub struct CircleProgram {
+    program: WebGlProgram,
+    mmatrix: WebGlUniformLocation,
+    round_dots: WebGlUniformLocation,
+    tint: WebGlUniformLocation,
+    //The tint passed to set_tint and the one last uploaded, if any.
+    tint_value: std::cell::Cell<[f32; 4]>,
+    tint_uploaded: std::cell::Cell<Option<[f32; 4]>>,
+    position: u32,
+    radius: u32,
+    color: u32,
+    positions: Buffer,
+    radii: Buffer,
+    colors: Buffer,
+    //Only for programs from new_instanced.
+    quads: Option<Quads>,
+    ctx: WebGl2RenderingContext,
+}
+
+struct Quads {
+    viewport: WebGlUniformLocation,
+    corner: u32,
+    corners: Buffer,
+}
+
+impl Drop for CircleProgram {
+    fn drop(&mut self) {
+        if !self.ctx.is_context_lost() {
+            self.ctx.delete_program(Some(&self.program));
+        }
+    }
+}
+
+impl CircleProgram {
+    pub fn new(ctx: &WebGl2RenderingContext) -> Result<Self, String> {
+        Self::with_shaders(ctx, CIRCLE_VERT_SHADER_STR, CIRCLE_FRAG_SHADER_STR, false)
+    }
+
+    ///
+    /// Same as [`CircleProgram::new`] except circles are drawn as instanced quads,
+    /// so that they can be bigger than the max point size.
+    ///
+    pub fn new_instanced(ctx: &WebGl2RenderingContext) -> Result<Self, String> {
+        Self::with_shaders(ctx, QUAD_VERT_SHADER_STR, QUAD_FRAG_SHADER_STR, true)
+    }
+
+    fn with_shaders(
+        ctx: &WebGl2RenderingContext,
+        vs: &str,
+        fs: &str,
+        instanced: bool,
+    ) -> Result<Self, String> {
+        let vert_shader = compile_shader(ctx, WebGl2RenderingContext::VERTEX_SHADER, vs)?;
+        let frag_shader = compile_shader(ctx, WebGl2RenderingContext::FRAGMENT_SHADER, fs)?;
+        let program = link_program(ctx, &vert_shader, &frag_shader)?;
+
+        ctx.delete_shader(Some(&vert_shader));
+        ctx.delete_shader(Some(&frag_shader));
+
+        let mmatrix = uniform_location(ctx, &program, "mmatrix")?;
+        let round_dots = uniform_location(ctx, &program, "round_dots")?;
+        let tint = uniform_location(ctx, &program, "tint")?;
+
+        let position = attrib_location(ctx, &program, "position")?;
+        let radius = attrib_location(ctx, &program, "radius")?;
+        let color = attrib_location(ctx, &program, "color")?;
+
+        let quads = if instanced {
+            Some(Quads {
+                viewport: uniform_location(ctx, &program, "viewport")?,
+                corner: attrib_location(ctx, &program, "corner")?,
+                corners: Buffer::new(ctx)?,
+            })
+        } else {
+            None
+        };
+
+        Ok(CircleProgram {
+            program,
+            mmatrix,
+            round_dots,
+            tint,
+            tint_value: std::cell::Cell::new([1.0; 4]),
+            tint_uploaded: std::cell::Cell::new(None),
+            position,
+            radius,
+            color,
+            positions: Buffer::new(ctx)?,
+            radii: Buffer::new(ctx)?,
+            colors: Buffer::new(ctx)?,
+            quads,
+            ctx: ctx.clone(),
+        })
+    }
+
+    ///
+    /// Set the color every circle is multiplied by. Keeps its value across draws.
+    /// Defaults to white, which leaves colors unchanged.
+    /// The uniform is uploaded by the next draw, and only if it changed.
+    ///
+    pub fn set_tint(&self, tint: &[f32; 4]) {
+        self.tint_value.set(*tint);
+    }
+
+    ///
+    /// Draw one circle per center. Panics if the slices have different lengths.
+    ///
+    pub fn draw(
+        &mut self,
+        mmatrix: &[f32; 9],
+        centers: &[[f32; 2]],
+        radii: &[f32],
+        colors: &[[f32; 4]],
+    ) {
+        self.draw_dots(mmatrix, centers, radii, colors, DotShape::Round);
+    }
+
+    ///
+    /// Same as [`CircleProgram::draw`] but with a choice of shape.
+    /// For squares the radius is half the side length.
+    ///
+    pub fn draw_dots(
+        &mut self,
+        mmatrix: &[f32; 9],
+        centers: &[[f32; 2]],
+        radii: &[f32],
+        colors: &[[f32; 4]],
+        shape: DotShape,
+    ) {
+        assert_eq!(centers.len(), radii.len());
+        assert_eq!(centers.len(), colors.len());
+
+        if centers.is_empty() {
+            return;
+        }
+
+        let ctx = &self.ctx;
+        ctx.use_program(Some(&self.program));
+        ctx.uniform_matrix3fv_with_f32_array(Some(&self.mmatrix), false, mmatrix);
+        ctx.uniform1i(Some(&self.round_dots), (shape == DotShape::Round) as i32);
+        let tint = self.tint_value.get();
+        if self.tint_uploaded.get() != Some(tint) {
+            ctx.uniform4fv_with_f32_array(Some(&self.tint), &tint);
+            self.tint_uploaded.set(Some(tint));
+        }
+
+        upload(ctx, &self.positions, centers, self.position, 2);
+        upload(ctx, &self.radii, radii, self.radius, 1);
+        upload(ctx, &self.colors, colors, self.color, 4);
+
+        match &self.quads {
+            Some(quads) => {
+                ctx.uniform2f(
+                    Some(&quads.viewport),
+                    ctx.drawing_buffer_width() as f32,
+                    ctx.drawing_buffer_height() as f32,
+                );
+                upload(ctx, &quads.corners, &CORNERS, quads.corner, 2);
+
+                ctx.vertex_attrib_divisor(self.position, 1);
+                ctx.vertex_attrib_divisor(self.radius, 1);
+                ctx.vertex_attrib_divisor(self.color, 1);
+
+                ctx.draw_arrays_instanced(
+                    WebGl2RenderingContext::TRIANGLES,
+                    0,
+                    CORNERS.len() as i32,
+                    centers.len() as i32,
+                );
+
+                //The divisors stick to the attribute slots, which other programs reuse.
+                ctx.vertex_attrib_divisor(self.position, 0);
+                ctx.vertex_attrib_divisor(self.radius, 0);
+                ctx.vertex_attrib_divisor(self.color, 0);
+                ctx.disable_vertex_attrib_array(quads.corner);
+            }
+            None => {
+                ctx.draw_arrays(WebGl2RenderingContext::POINTS, 0, centers.len() as i32);
+            }
+        }
+
+        //Other programs only enable the attributes they use.
+        ctx.disable_vertex_attrib_array(self.position);
+        ctx.disable_vertex_attrib_array(self.radius);
+        ctx.disable_vertex_attrib_array(self.color);
+    }
+}
+
+fn upload<T: Copy>(
+    ctx: &WebGl2RenderingContext,
+    buffer: &Buffer,
+    data: &[T],
+    attrib: u32,
+    size: i32,
+) {
+    ctx.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer.buffer));
+
+    let n_bytes = std::mem::size_of_val(data);
+    let points_buf: &[u8] =
+        unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, n_bytes) };
+
+    ctx.buffer_data_with_u8_array(
+        WebGl2RenderingContext::ARRAY_BUFFER,
+        points_buf,
+        WebGl2RenderingContext::DYNAMIC_DRAW,
+    );
+
+    ctx.vertex_attrib_pointer_with_i32(attrib, size, WebGl2RenderingContext::FLOAT, false, 0, 0);
+    ctx.enable_vertex_attrib_array(attrib);
+}
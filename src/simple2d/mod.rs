@@ -8,21 +8,26 @@
 //!
 use gloo::console::log;
 use web_sys::WebGl2RenderingContext;
+mod circle;
 mod shader;
 
-use shader::*;
+pub use circle::{CircleProgram, DotShape};
 
-pub use shader::Buffer;
+pub use shader::{
+    ActiveVariable, BlendMode, Buffer, GlProgram, GlProgramBuilder, Primitive, ProgramReflection,
+    ProgramVariants,
+};
 
 const SQUARE_FRAG_SHADER_STR: &str = r#"#version 300 es
 precision mediump float;
 out vec4 out_color;
 uniform vec4 bg;
+uniform vec4 tint;
 
 void main() {
     //coord is between -0.5 and 0.5
     vec2 coord = gl_PointCoord - vec2(0.5,0.5);         
-    out_color = bg;
+    out_color = bg * tint;
 }
 "#;
 
@@ -30,6 +35,7 @@ const CIRCLE_FRAG_SHADER_STR: &str = r#"#version 300 es
 precision mediump float;
 out vec4 out_color;
 uniform vec4 bg;
+uniform vec4 tint;
 
 void main() {
     //coord is between -0.5 and 0.5
@@ -38,7 +44,7 @@ void main() {
     if(dissqr > 0.25){
         discard;
     }
-    out_color = bg;    
+    out_color = bg * tint;    
 }
 "#;
 
@@ -106,8 +112,10 @@ impl StaticBuffer {
 
 ///
 /// A buffer make with [`WebGl2RenderingContext::DYNAMIC_DRAW`].
+/// Updates that fit in the space already allocated reuse it with `bufferSubData`.
 ///
-pub struct DynamicBuffer(Buffer);
+//The second field is the number of vertices allocated on the gpu.
+pub struct DynamicBuffer(Buffer, usize);
 
 impl std::ops::Deref for DynamicBuffer {
     type Target = Buffer;
@@ -133,7 +141,31 @@ impl std::ops::Deref for DynamicBuffer {
 // }
 impl DynamicBuffer {
     pub fn new(ctx: &WebGl2RenderingContext) -> Result<Self, String> {
-        Ok(DynamicBuffer(Buffer::new(ctx)?))
+        Ok(DynamicBuffer(Buffer::new(ctx)?, 0))
+    }
+
+    ///
+    /// Create a buffer with space for `num_verts` vertices already allocated,
+    /// so updates up to that size never reallocate.
+    ///
+    pub fn with_capacity(ctx: &WebGl2RenderingContext, num_verts: usize) -> Result<Self, String> {
+        let buffer = Buffer::new(ctx)?;
+
+        ctx.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer.buffer));
+        ctx.buffer_data_with_i32(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            (num_verts * std::mem::size_of::<Vertex>()) as i32,
+            WebGl2RenderingContext::DYNAMIC_DRAW,
+        );
+
+        Ok(DynamicBuffer(buffer, num_verts))
+    }
+
+    ///
+    /// The number of vertices that fit in the space currently allocated on the gpu.
+    ///
+    pub fn capacity(&self) -> usize {
+        self.1
     }
 
     pub fn update_clear(&mut self, verts: &mut Vec<Vertex>) {
@@ -141,32 +173,68 @@ impl DynamicBuffer {
         verts.clear();
     }
     pub fn update_no_clear(&mut self, vertices: &[Vertex]) {
-        let ctx = &self.0.ctx;
+        let n_bytes = vertices.len() * std::mem::size_of::<Vertex>();
+        let points_buf: &[u8] =
+            unsafe { std::slice::from_raw_parts(vertices.as_ptr() as *const u8, n_bytes) };
 
-        self.0.num_verts = vertices.len();
+        self.upload(points_buf, vertices.len());
+    }
 
-        ctx.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&self.0.buffer));
+    ///
+    /// Same as [`DynamicBuffer::update_no_clear`] except the vertices are already
+    /// flattened into `[x0,y0,x1,y1,...]`. Panics if the length is not a multiple of 2.
+    ///
+    pub fn update_flat(&mut self, vertices: &[f32]) {
+        assert_eq!(
+            vertices.len() % 2,
+            0,
+            "flat vertex data must be a multiple of 2"
+        );
 
-        let n_bytes = vertices.len() * std::mem::size_of::<Vertex>();
+        let n_bytes = std::mem::size_of_val(vertices);
         let points_buf: &[u8] =
             unsafe { std::slice::from_raw_parts(vertices.as_ptr() as *const u8, n_bytes) };
 
-        ctx.buffer_data_with_u8_array(
-            WebGl2RenderingContext::ARRAY_BUFFER,
-            points_buf,
-            WebGl2RenderingContext::DYNAMIC_DRAW,
-        );
+        self.upload(points_buf, vertices.len() / 2);
+    }
+
+    fn upload(&mut self, points_buf: &[u8], num_verts: usize) {
+        let ctx = &self.0.ctx;
+
+        self.0.num_verts = num_verts;
+
+        ctx.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&self.0.buffer));
+
+        if num_verts <= self.1 {
+            ctx.buffer_sub_data_with_i32_and_u8_array(
+                WebGl2RenderingContext::ARRAY_BUFFER,
+                0,
+                points_buf,
+            );
+        } else {
+            ctx.buffer_data_with_u8_array(
+                WebGl2RenderingContext::ARRAY_BUFFER,
+                points_buf,
+                WebGl2RenderingContext::DYNAMIC_DRAW,
+            );
+            self.1 = num_verts;
+        }
     }
 }
 
 struct Args<'a> {
     pub verts: &'a Buffer,
-    pub primitive: u32,
+    pub primitive: Primitive,
     pub game_dim: [f32; 2],
     pub as_square: bool,
     pub color: &'a [f32; 4],
     pub offset: [f32; 2],
     pub point_size: f32,
+    pub wireframe_overlay: Option<&'a [f32; 4]>,
+    pub blend: Option<BlendMode>,
+    pub batch: Option<&'a [[f32; 2]]>,
+    pub scissor: Option<[i32; 4]>,
+    pub tint: [f32; 4],
 }
 
 // pub struct CpuBuffer<T> {
@@ -210,11 +278,46 @@ impl CtxWrap {
     ///
     pub fn setup_alpha(&self) {
         self.disable(WebGl2RenderingContext::DEPTH_TEST);
-        self.enable(WebGl2RenderingContext::BLEND);
-        self.blend_func(
-            WebGl2RenderingContext::SRC_ALPHA,
-            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
-        );
+        BlendMode::Alpha.apply(self);
+    }
+
+    ///
+    /// Start drawing a mask. Until [`CtxWrap::begin_stencil_test`], draws only write `1`
+    /// into the stencil buffer instead of drawing colors.
+    /// The context needs a stencil buffer, see [`crate::utils::get_context_webgl2_offscreen_with_stencil`].
+    ///
+    /// Usage:
+    /// 1. `begin_stencil_write`, then draw the mask shapes.
+    /// 2. `begin_stencil_test`, then draw what should be clipped to the mask.
+    /// 3. `end_stencil`.
+    ///
+    pub fn begin_stencil_write(&self) {
+        type C = WebGl2RenderingContext;
+        self.enable(C::STENCIL_TEST);
+        self.clear_stencil(0);
+        self.clear(C::STENCIL_BUFFER_BIT);
+        self.stencil_func(C::ALWAYS, 1, 0xff);
+        self.stencil_op(C::KEEP, C::KEEP, C::REPLACE);
+        self.color_mask(false, false, false, false);
+    }
+
+    ///
+    /// Stop writing the mask started by [`CtxWrap::begin_stencil_write`]. Following draws
+    /// only show where the mask was drawn, or outside of it if `inverted` is true.
+    ///
+    pub fn begin_stencil_test(&self, inverted: bool) {
+        type C = WebGl2RenderingContext;
+        self.color_mask(true, true, true, true);
+        self.stencil_func(if inverted { C::NOTEQUAL } else { C::EQUAL }, 1, 0xff);
+        self.stencil_op(C::KEEP, C::KEEP, C::KEEP);
+    }
+
+    ///
+    /// Stop masking.
+    ///
+    pub fn end_stencil(&self) {
+        self.color_mask(true, true, true, true);
+        self.disable(WebGl2RenderingContext::STENCIL_TEST);
     }
     pub fn buffer_dynamic(&self) -> DynamicBuffer {
         DynamicBuffer::new(self).unwrap_throw()
@@ -244,10 +347,22 @@ impl CtxWrap {
     // }
 
     pub fn draw_clear(&self, color: [f32; 4]) {
+        self.clear_screen(color, false);
+    }
+
+    ///
+    /// Clear the screen to `color`, and the depth buffer too if `depth` is true.
+    /// Call at the start of every frame.
+    ///
+    pub fn clear_screen(&self, color: [f32; 4], depth: bool) {
         let [a, b, c, d] = color;
         self.ctx.clear_color(a, b, c, d);
-        self.ctx
-            .clear(web_sys::WebGl2RenderingContext::COLOR_BUFFER_BIT);
+
+        let mut mask = WebGl2RenderingContext::COLOR_BUFFER_BIT;
+        if depth {
+            mask |= WebGl2RenderingContext::DEPTH_BUFFER_BIT;
+        }
+        self.ctx.clear(mask);
     }
 }
 
@@ -272,20 +387,82 @@ impl From<axgeom::Rect<f32>> for Rect {
     }
 }
 
+///
+/// Compute the axis aligned bounding rect of some vertices before they are uploaded.
+/// Returns `None` if there are no vertices.
+///
+pub fn bounding_rect(verts: &[Vertex]) -> Option<Rect> {
+    let (first, rest) = verts.split_first()?;
+    let [mut minx, mut miny] = *first;
+    let [mut maxx, mut maxy] = *first;
+    for &[x, y] in rest {
+        minx = minx.min(x);
+        miny = miny.min(y);
+        maxx = maxx.max(x);
+        maxy = maxy.max(y);
+    }
+    Some(Rect {
+        x: minx,
+        y: miny,
+        w: maxx - minx,
+        h: maxy - miny,
+    })
+}
+
+///
+/// Compute a bounding circle of some vertices before they are uploaded.
+/// The center is the center of the [`bounding_rect`].
+/// Returns the center and radius, or `None` if there are no vertices.
+///
+pub fn bounding_circle(verts: &[Vertex]) -> Option<([f32; 2], f32)> {
+    let rect = bounding_rect(verts)?;
+    let center = [rect.x + rect.w / 2.0, rect.y + rect.h / 2.0];
+    let radius = verts
+        .iter()
+        .map(|&[x, y]| {
+            let dx = x - center[0];
+            let dy = y - center[1];
+            dx * dx + dy * dy
+        })
+        .fold(0.0f32, f32::max)
+        .sqrt();
+    Some((center, radius))
+}
+
+//Maps game coordinates to clip space. See ShaderSystem::view.
+fn projection(dim:[f32;2],offset:[f32;2])->[f32;9]{
+    let scale=|scalex,scaley|{
+        [
+            scalex,0.,0.,
+            0.,scaley,0.,
+            0.,0.,1.]
+    };
+
+    let translation=|tx,ty|{
+        [
+            1., 0., 0.,
+            0., 1., 0.,
+            tx, ty, 1.,
+          ]
+    };
+    use webgl_matrix::prelude::*;
+    
+    let mut a3=translation(-dim[0]/2.+offset[0],-dim[1]/2.+offset[1]);
+    let a1=scale(2.0,-2.0);
+    let a2=scale(1.0/dim[0],1.0/dim[1]);
+    a3.mul(&a1).mul(&a2);
+    a3    
+}
+
 ///
 /// A simple shader program that allows the user to draw simple primitives.
 ///
 pub struct ShaderSystem {
     circle_program: GlProgram,
     square_program: GlProgram,
-    ctx: WebGl2RenderingContext
-}
-
-impl Drop for ShaderSystem {
-    fn drop(&mut self) {
-        self.ctx.delete_program(Some(&self.circle_program.program));
-        self.ctx.delete_program(Some(&self.square_program.program));
-    }
+    ctx: WebGl2RenderingContext,
+    pixel_ratio: f32,
+    max_point_size: f32,
 }
 
 impl ShaderSystem {
@@ -293,13 +470,32 @@ impl ShaderSystem {
         let circle_program = GlProgram::new(ctx, VERT_SHADER_STR, CIRCLE_FRAG_SHADER_STR)?;
         let square_program = GlProgram::new(ctx, VERT_SHADER_STR, SQUARE_FRAG_SHADER_STR)?;
 
+        let max_point_size = ctx
+            .get_parameter(WebGl2RenderingContext::ALIASED_POINT_SIZE_RANGE)
+            .ok()
+            .and_then(|r| js_sys::Reflect::get_u32(&r, 1).ok())
+            .and_then(|m| m.as_f64())
+            .unwrap_or(f64::MAX) as f32;
+
         Ok(ShaderSystem {
             circle_program,
             square_program,
-            ctx: ctx.clone()
+            ctx: ctx.clone(),
+            pixel_ratio: 1.0,
+            max_point_size,
         })
     }
 
+    ///
+    /// Set the device pixel ratio, e.g. from [`crate::utils::device_pixel_ratio`] sent over by
+    /// the main thread. Point sizes passed to [`View::draw_squares`] and [`View::draw_circles`]
+    /// are multiplied by it so they look the same size on high-dpi displays.
+    /// Point sizes are also clamped to the largest size the gpu supports.
+    ///
+    pub fn set_pixel_ratio(&mut self, pixel_ratio: f32) {
+        self.pixel_ratio = pixel_ratio;
+    }
+
     fn draw(&mut self, args: Args) {
         let Args {
             verts,
@@ -309,43 +505,63 @@ impl ShaderSystem {
             color,
             offset,
             point_size,
+            wireframe_overlay,
+            blend,
+            batch,
+            scissor,
+            tint,
         } = args;
 
         assert_eq!(verts.ctx, self.ctx);
 
-        fn projection(dim:[f32;2],offset:[f32;2])->[f32;9]{
-            let scale=|scalex,scaley|{
-                [
-                    scalex,0.,0.,
-                    0.,scaley,0.,
-                    0.,0.,1.]
-            };
-    
-            let translation=|tx,ty|{
-                [
-                    1., 0., 0.,
-                    0., 1., 0.,
-                    tx, ty, 1.,
-                  ]
-            };
-            use webgl_matrix::prelude::*;
-            
-            let mut a3=translation(-dim[0]/2.+offset[0],-dim[1]/2.+offset[1]);
-            let a1=scale(2.0,-2.0);
-            let a2=scale(1.0/dim[0],1.0/dim[1]);
-            a3.mul(&a1).mul(&a2);
-            a3    
-        }
-        
         let matrix=projection(game_dim,offset);
 
-        if as_square {
-            self.square_program
-                .draw(verts, primitive, &matrix, point_size, color);
+        let point_size = (point_size * self.pixel_ratio).min(self.max_point_size);
+
+        let program = if as_square {
+            &self.square_program
         } else {
-            self.circle_program
-                .draw(verts, primitive, &matrix, point_size, color);
+            &self.circle_program
         };
+        //Only recorded here, the draw uploads it if it changed.
+        program.set_tint(&tint);
+
+        self.with_state(blend, scissor, || {
+            if let Some(batch) = batch {
+                let matrices: Vec<[f32; 9]> = batch
+                    .iter()
+                    .map(|o| projection(game_dim, [offset[0] + o[0], offset[1] + o[1]]))
+                    .collect();
+                program.draw_batch(verts, primitive, &matrices, point_size, color);
+            } else {
+                program.draw(verts, primitive, &matrix, point_size, color);
+
+                if let Some(line_color) = wireframe_overlay {
+                    self.square_program.set_tint(&tint);
+                    self.square_program
+                        .draw_wireframe(verts, &matrix, line_color);
+                }
+            }
+        });
+    }
+
+    //Apply the blend mode and scissor rectangle of a draw around `f`.
+    //The scissor test is disabled again afterwards, the blend mode is left as is.
+    fn with_state(&self, blend: Option<BlendMode>, scissor: Option<[i32; 4]>, f: impl FnOnce()) {
+        if let Some(blend) = blend {
+            blend.apply(&self.ctx);
+        }
+
+        if let Some([x, y, w, h]) = scissor {
+            self.ctx.enable(WebGl2RenderingContext::SCISSOR_TEST);
+            self.ctx.scissor(x, y, w, h);
+        }
+
+        f();
+
+        if scissor.is_some() {
+            self.ctx.disable(WebGl2RenderingContext::SCISSOR_TEST);
+        }
     }
 
     ///
@@ -358,6 +574,9 @@ impl ShaderSystem {
             sys: self,
             offset: offset.into(),
             dim: game_dim.into(),
+            blend: None,
+            scissor: None,
+            tint: [1.0; 4],
         }
     }
 }
@@ -369,40 +588,166 @@ pub struct View<'a> {
     sys: &'a mut ShaderSystem,
     offset: [f32; 2],
     dim: [f32; 2],
+    blend: Option<BlendMode>,
+    scissor: Option<[i32; 4]>,
+    tint: [f32; 4],
 }
 impl View<'_> {
+    ///
+    /// Draw the same triangles once per offset. The program, color and vertex
+    /// attributes are only set up once for the whole batch.
+    ///
+    pub fn draw_triangles_batch(
+        &mut self,
+        verts: &Buffer,
+        color: &[f32; 4],
+        offsets: &[[f32; 2]],
+    ) {
+        self.sys.draw(Args {
+            verts,
+            primitive: Primitive::Triangles,
+            game_dim: self.dim,
+            as_square: true,
+            color,
+            offset: self.offset,
+            point_size: 0.0,
+            wireframe_overlay: None,
+            blend: self.blend,
+            batch: Some(offsets),
+            scissor: self.scissor,
+            tint: self.tint,
+        })
+    }
+
+    ///
+    /// Set the blend mode before every draw through this view.
+    /// By default the blend state is left as is, e.g. from [`CtxWrap::setup_alpha`].
+    ///
+    pub fn set_blend_mode(&mut self, blend: BlendMode) {
+        self.blend = Some(blend);
+    }
+
+    ///
+    /// Only draw inside the rectangle `[x, y, width, height]`, in pixels.
+    /// Like webgl's scissor, the origin is the bottom left corner of the canvas.
+    /// `None` (the default) draws everywhere.
+    ///
+    pub fn set_scissor(&mut self, scissor: Option<[i32; 4]>) {
+        self.scissor = scissor;
+    }
+
+    ///
+    /// Multiply the color of everything drawn through this view, e.g. for fades or hit flashes.
+    /// Defaults to white, which leaves colors unchanged.
+    ///
+    pub fn set_tint(&mut self, tint: [f32; 4]) {
+        self.tint = tint;
+    }
+
     pub fn draw_squares(&mut self, verts: &Buffer, point_size: f32, color: &[f32; 4]) {
         self.sys.draw(Args {
             verts,
-            primitive: WebGl2RenderingContext::POINTS,
+            primitive: Primitive::Points,
             game_dim: self.dim,
             as_square: true,
             color,
             offset: self.offset,
             point_size,
+            wireframe_overlay: None,
+            blend: self.blend,
+            batch: None,
+            scissor: self.scissor,
+            tint: self.tint,
         })
     }
     pub fn draw_triangles(&mut self, verts: &Buffer, color: &[f32; 4]) {
         self.sys.draw(Args {
             verts,
-            primitive: WebGl2RenderingContext::TRIANGLES,
+            primitive: Primitive::Triangles,
+            game_dim: self.dim,
+            as_square: true,
+            color,
+            offset: self.offset,
+            point_size: 0.0,
+            wireframe_overlay: None,
+            blend: self.blend,
+            batch: None,
+            scissor: self.scissor,
+            tint: self.tint,
+        })
+    }
+
+    ///
+    /// Draw the triangles and then outline each of them with `line_color`.
+    /// Useful as a debug view.
+    ///
+    pub fn draw_triangles_wireframe(
+        &mut self,
+        verts: &Buffer,
+        color: &[f32; 4],
+        line_color: &[f32; 4],
+    ) {
+        self.sys.draw(Args {
+            verts,
+            primitive: Primitive::Triangles,
             game_dim: self.dim,
             as_square: true,
             color,
             offset: self.offset,
             point_size: 0.0,
+            wireframe_overlay: Some(line_color),
+            blend: self.blend,
+            batch: None,
+            scissor: self.scissor,
+            tint: self.tint,
         })
     }
 
+    ///
+    /// Draw antialiased circles with a [`CircleProgram`], each with its own radius in pixels and color.
+    ///
+    pub fn draw_circles_varying(
+        &mut self,
+        program: &mut CircleProgram,
+        centers: &[[f32; 2]],
+        radii: &[f32],
+        colors: &[[f32; 4]],
+    ) {
+        self.draw_dots(program, centers, radii, colors, DotShape::Round);
+    }
+
+    ///
+    /// Same as [`View::draw_circles_varying`] but with a choice of shape.
+    ///
+    pub fn draw_dots(
+        &mut self,
+        program: &mut CircleProgram,
+        centers: &[[f32; 2]],
+        radii: &[f32],
+        colors: &[[f32; 4]],
+        shape: DotShape,
+    ) {
+        let matrix = projection(self.dim, self.offset);
+        program.set_tint(&self.tint);
+        self.sys.with_state(self.blend, self.scissor, || {
+            program.draw_dots(&matrix, centers, radii, colors, shape);
+        });
+    }
+
     pub fn draw_circles(&mut self, verts: &Buffer, point_size: f32, color: &[f32; 4]) {
         self.sys.draw(Args {
             verts,
-            primitive: WebGl2RenderingContext::POINTS,
+            primitive: Primitive::Points,
             game_dim: self.dim,
             as_square: false,
             color,
             offset: self.offset,
             point_size,
+            wireframe_overlay: None,
+            blend: self.blend,
+            batch: None,
+            scissor: self.scissor,
+            tint: self.tint,
         })
     }
 }
@@ -487,6 +832,90 @@ impl<'a> ShapeBuilder<'a> {
         self
     }
 
+    ///
+    /// A thick line through all the points, made of triangles.
+    /// Interior corners are joined with a miter so there are no gaps.
+    /// Unlike `LINES`, the thickness is consistent across gpus.
+    ///
+    pub fn polyline(&mut self, radius: f32, points: &[[f32; 2]]) -> &mut Self {
+        use axgeom::*;
+
+        let mut pts: Vec<Vec2<f32>> = Vec::with_capacity(points.len());
+        for &p in points {
+            let p = Vec2::from(p);
+            //Skip repeated points since they have no direction.
+            if pts.last().map(|&l| (p - l).magnitude2() > 0.0).unwrap_or(true) {
+                pts.push(p);
+            }
+        }
+
+        if pts.len() < 2 {
+            return self;
+        }
+
+        let normal = |a: Vec2<f32>, b: Vec2<f32>| (b - a).rotate_90deg_right().normalize_to(1.0);
+
+        let offsets: Vec<Vec2<f32>> = (0..pts.len())
+            .map(|i| {
+                if i == 0 {
+                    normal(pts[0], pts[1]) * radius
+                } else if i == pts.len() - 1 {
+                    normal(pts[i - 1], pts[i]) * radius
+                } else {
+                    let n1 = normal(pts[i - 1], pts[i]);
+                    let n2 = normal(pts[i], pts[i + 1]);
+                    let m = n1 + n2;
+                    if m.magnitude2() == 0.0 {
+                        //The line doubles back on itself.
+                        return n1 * radius;
+                    }
+                    let m = m.normalize_to(1.0);
+                    let cos = m.x * n1.x + m.y * n1.y;
+                    //Limit how far sharp corners can stick out.
+                    m * (radius / cos.max(0.25))
+                }
+            })
+            .collect();
+
+        for i in 0..pts.len() - 1 {
+            let (a, b) = (pts[i], pts[i + 1]);
+            let (oa, ob) = (offsets[i], offsets[i + 1]);
+            let arr: [[f32; 2]; 6] = [
+                (a + oa).into(),
+                (a - oa).into(),
+                (b + ob).into(),
+                (a - oa).into(),
+                (b + ob).into(),
+                (b - ob).into(),
+            ];
+            self.inner.extend(arr);
+        }
+        self
+    }
+
+    ///
+    /// Expand each point into a square of two triangles, to be drawn with [`View::draw_triangles`].
+    /// Unlike [`View::draw_squares`] this is not limited by the max point size of the gpu,
+    /// at the cost of sending six vertices per point instead of one.
+    /// [`CircleProgram::new_instanced`] gets around the limit without the extra vertices.
+    ///
+    pub fn point_quads(
+        &mut self,
+        radius: f32,
+        points: impl IntoIterator<Item = impl Into<[f32; 2]>>,
+    ) -> &mut Self {
+        for p in points {
+            let [x, y] = p.into();
+            self.rect(Rect {
+                x: x - radius,
+                y: y - radius,
+                w: radius * 2.0,
+                h: radius * 2.0,
+            });
+        }
+        self
+    }
+
     pub fn rect(&mut self, rect: impl Into<Rect>) -> &mut Self {
         use axgeom::vec2;
         let rect: Rect = rect.into();
@@ -534,3 +963,32 @@ pub fn convert_coord(canvas: &web_sys::HtmlElement, e: &web_sys::MouseEvent) ->
     let [x, y] = [(x - rect.left()) * scalex, (y - rect.top()) * scaley];
     [x as f32, y as f32]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect_of(verts: &[Vertex]) -> Option<[f32; 4]> {
+        bounding_rect(verts).map(|r| [r.x, r.y, r.w, r.h])
+    }
+
+    #[test]
+    fn bounding_rect_of_vertices() {
+        assert_eq!(rect_of(&[]), None);
+        assert_eq!(rect_of(&[[3.0, -2.0]]), Some([3.0, -2.0, 0.0, 0.0]));
+        assert_eq!(
+            rect_of(&[[1.0, 5.0], [-1.0, 2.0], [4.0, 3.0]]),
+            Some([-1.0, 2.0, 5.0, 3.0])
+        );
+    }
+
+    #[test]
+    fn bounding_circle_of_vertices() {
+        assert_eq!(bounding_circle(&[]), None);
+        assert_eq!(bounding_circle(&[[3.0, -2.0]]), Some(([3.0, -2.0], 0.0)));
+        assert_eq!(
+            bounding_circle(&[[0.0, 0.0], [6.0, 0.0], [6.0, 8.0], [0.0, 8.0]]),
+            Some(([3.0, 4.0], 5.0))
+        );
+    }
+}
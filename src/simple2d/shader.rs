@@ -2,6 +2,67 @@ use web_sys::WebGlShader;
 use web_sys::WebGlUniformLocation;
 use web_sys::{WebGl2RenderingContext, WebGlProgram};
 
+///
+/// How drawn fragments are combined with what is already in the framebuffer.
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Blending disabled.
+    None,
+    /// `(SRC_ALPHA, ONE_MINUS_SRC_ALPHA)`
+    Alpha,
+    /// `(SRC_ALPHA, ONE)`
+    Additive,
+    /// `(ONE, ONE_MINUS_SRC_ALPHA)` for colors that are already multiplied by alpha.
+    Premultiplied,
+}
+
+impl BlendMode {
+    pub fn apply(&self, ctx: &WebGl2RenderingContext) {
+        type C = WebGl2RenderingContext;
+        let (src, dst) = match self {
+            BlendMode::None => {
+                ctx.disable(C::BLEND);
+                return;
+            }
+            BlendMode::Alpha => (C::SRC_ALPHA, C::ONE_MINUS_SRC_ALPHA),
+            BlendMode::Additive => (C::SRC_ALPHA, C::ONE),
+            BlendMode::Premultiplied => (C::ONE, C::ONE_MINUS_SRC_ALPHA),
+        };
+        ctx.enable(C::BLEND);
+        ctx.blend_func(src, dst);
+    }
+}
+
+///
+/// The kinds of primitives the vertices of a buffer can be drawn as.
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Primitive {
+    Points,
+    Lines,
+    LineStrip,
+    LineLoop,
+    Triangles,
+    TriangleStrip,
+    TriangleFan,
+}
+
+impl Primitive {
+    pub fn to_gl(self) -> u32 {
+        type C = WebGl2RenderingContext;
+        match self {
+            Primitive::Points => C::POINTS,
+            Primitive::Lines => C::LINES,
+            Primitive::LineStrip => C::LINE_STRIP,
+            Primitive::LineLoop => C::LINE_LOOP,
+            Primitive::Triangles => C::TRIANGLES,
+            Primitive::TriangleStrip => C::TRIANGLE_STRIP,
+            Primitive::TriangleFan => C::TRIANGLE_FAN,
+        }
+    }
+}
+
 ///
 /// A webgl2 buffer that automatically deletes itself when dropped.
 ///
@@ -22,7 +83,10 @@ impl Buffer {
 }
 impl Drop for Buffer {
     fn drop(&mut self) {
-        self.ctx.delete_buffer(Some(&self.buffer));
+        //Everything was already freed along with the lost context.
+        if !self.ctx.is_context_lost() {
+            self.ctx.delete_buffer(Some(&self.buffer));
+        }
     }
 }
 
@@ -30,7 +94,7 @@ impl GlProgram {
     pub fn draw(
         &self,
         buffer: &Buffer,
-        primitive: u32,
+        primitive: Primitive,
         mmatrix: &[f32; 9],
         point_size: f32,
         color: &[f32; 4],
@@ -39,10 +103,106 @@ impl GlProgram {
             return;
         }
 
+        self.setup(buffer, mmatrix, point_size, color);
+
+        buffer
+            .ctx
+            .draw_arrays(primitive.to_gl(), 0, buffer.num_verts as i32);
+    }
+
+    ///
+    /// Draw the buffer once per matrix, only setting up the program and attributes once.
+    ///
+    pub fn draw_batch(
+        &self,
+        buffer: &Buffer,
+        primitive: Primitive,
+        mmatrices: &[[f32; 9]],
+        point_size: f32,
+        color: &[f32; 4],
+    ) {
+        let (first, rest) = match mmatrices.split_first() {
+            Some(a) => a,
+            None => return,
+        };
+        if buffer.num_verts == 0 {
+            return;
+        }
+
+        self.setup(buffer, first, point_size, color);
+        let context = &buffer.ctx;
+        let primitive = primitive.to_gl();
+        context.draw_arrays(primitive, 0, buffer.num_verts as i32);
+
+        for mmatrix in rest {
+            context.uniform_matrix3fv_with_f32_array(Some(&self.mmatrix), false, mmatrix);
+            context.draw_arrays(primitive, 0, buffer.num_verts as i32);
+        }
+    }
+
+    ///
+    /// Draw the outline of every triangle in the buffer, as one draw call of lines
+    /// going through an index buffer that is kept and only grown when needed.
+    ///
+    pub fn draw_wireframe(&self, buffer: &Buffer, mmatrix: &[f32; 9], color: &[f32; 4]) {
+        let num_triangles = buffer.num_verts / 3;
+        if num_triangles == 0 {
+            return;
+        }
+
+        self.setup(buffer, mmatrix, 0.0, color);
+
+        let ctx = &buffer.ctx;
+        let mut edges = self.edges.borrow_mut();
+        let covered = edges.as_ref().map_or(0, |e| e.num_verts);
+        if covered < num_triangles * 3 {
+            let indices = edge_indices(num_triangles);
+            //Only fails if the context is lost, in which case nothing is drawn anyway.
+            let mut e = match Buffer::new(ctx) {
+                Ok(e) => e,
+                Err(_) => return,
+            };
+            ctx.bind_buffer(
+                WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
+                Some(&e.buffer),
+            );
+            let n_bytes = std::mem::size_of_val(indices.as_slice());
+            let bytes: &[u8] =
+                unsafe { std::slice::from_raw_parts(indices.as_ptr() as *const u8, n_bytes) };
+            ctx.buffer_data_with_u8_array(
+                WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
+                bytes,
+                WebGl2RenderingContext::STATIC_DRAW,
+            );
+            e.num_verts = num_triangles * 3;
+            *edges = Some(e);
+        }
+        let e = edges.as_ref().unwrap();
+        ctx.bind_buffer(
+            WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
+            Some(&e.buffer),
+        );
+        ctx.draw_elements_with_i32(
+            Primitive::Lines.to_gl(),
+            (num_triangles * 6) as i32,
+            WebGl2RenderingContext::UNSIGNED_INT,
+            0,
+        );
+    }
+
+    fn setup(&self, buffer: &Buffer, mmatrix: &[f32; 9], point_size: f32, color: &[f32; 4]) {
         let context = &buffer.ctx;
 
         context.use_program(Some(&self.program));
 
+        //Only uploaded when it changed, see set_tint.
+        if let (Some(loc), Some(tint)) = (&self.tint, self.tint_value.get()) {
+            if self.tint_uploaded.get() != Some(tint) {
+                context.uniform4fv_with_f32_array(Some(loc), &tint);
+                self.tint_uploaded.set(Some(tint));
+            }
+        }
+
         context.uniform1f(Some(&self.point_size), point_size);
         context.uniform4fv_with_f32_array(Some(&self.bg), color);
 
@@ -59,8 +219,6 @@ impl GlProgram {
             0,
         );
         context.enable_vertex_attrib_array(0);
-
-        context.draw_arrays(primitive, 0, buffer.num_verts as i32);
     }
 
     pub fn new(context: &WebGl2RenderingContext, vs: &str, fs: &str) -> Result<Self, String> {
@@ -71,40 +229,319 @@ impl GlProgram {
         context.delete_shader(Some(&vert_shader));
         context.delete_shader(Some(&frag_shader));
 
-        let mmatrix = context
-            .get_uniform_location(&program, "mmatrix")
-            .ok_or_else(|| "uniform err".to_string())?;
-        let point_size = context
-            .get_uniform_location(&program, "point_size")
-            .ok_or_else(|| "uniform err".to_string())?;
-        let bg = context
-            .get_uniform_location(&program, "bg")
-            .ok_or_else(|| "uniform err".to_string())?;
-        let position = context.get_attrib_location(&program, "position");
-        if position < 0 {
-            return Err("attribute err".to_string());
-        }
-        let position = position as u32;
+        let mmatrix = uniform_location(context, &program, "mmatrix")?;
+        let point_size = uniform_location(context, &program, "point_size")?;
+        let bg = uniform_location(context, &program, "bg")?;
+        //Optional, so that custom shaders don't have to declare it.
+        let tint = context.get_uniform_location(&program, "tint");
+        let position = attrib_location(context, &program, "position")?;
 
         Ok(GlProgram {
             program,
             mmatrix,
             point_size,
             bg,
+            tint,
+            tint_value: std::cell::Cell::new(None),
+            tint_uploaded: std::cell::Cell::new(None),
             position,
+            edges: std::cell::RefCell::new(None),
+            ctx: context.clone(),
         })
     }
+
+    ///
+    /// Log the locations of the uniforms and attributes used by the program,
+    /// along with everything [`GlProgram::reflect`] finds. Useful when nothing is drawn.
+    ///
+    pub fn debug_locations(&self) {
+        gloo::console::log!(format!(
+            "GlProgram: position attribute at {}, tint uniform {}",
+            self.position,
+            if self.tint.is_some() { "found" } else { "not declared" }
+        ));
+        let reflection = self.reflect();
+        for u in reflection.uniforms.iter() {
+            gloo::console::log!(format!("uniform {} {} [{}]", u.type_name, u.name, u.size));
+        }
+        for a in reflection.attributes.iter() {
+            let loc = self.ctx.get_attrib_location(&self.program, &a.name);
+            gloo::console::log!(format!(
+                "attribute {} {} [{}] at {}",
+                a.type_name, a.name, a.size, loc
+            ));
+        }
+    }
+
+    ///
+    /// Set the `tint` uniform, a color every fragment is multiplied by.
+    /// Keeps its value across draws. Does nothing if the shader doesn't declare it.
+    /// The uniform is uploaded by the next draw, and only if it changed.
+    ///
+    pub fn set_tint(&self, tint: &[f32; 4]) {
+        if self.tint.is_some() {
+            self.tint_value.set(Some(*tint));
+        }
+    }
+
+    ///
+    /// Recompile the program from new shader sources. On success the old program is deleted
+    /// and replaced. On failure the old program is kept and the error is returned.
+    /// The tint set with [`GlProgram::set_tint`] carries over.
+    ///
+    pub fn reload(&mut self, vs: &str, fs: &str) -> Result<(), String> {
+        let program = GlProgram::new(&self.ctx, vs, fs)?;
+        if let Some(tint) = self.tint_value.get() {
+            program.set_tint(&tint);
+        }
+        //The old program is deleted when it is dropped.
+        *self = program;
+        Ok(())
+    }
 }
 
+///
+/// A compiled shader program with the uniforms `mmatrix`, `point_size`, `bg`
+/// and the attribute `position`, and optionally the uniform `tint`.
+/// The program is deleted when dropped.
+///
 pub struct GlProgram {
     pub(crate) program: WebGlProgram,
     mmatrix: WebGlUniformLocation,
     point_size: WebGlUniformLocation,
     bg: WebGlUniformLocation,
+    tint: Option<WebGlUniformLocation>,
+    //The tint passed to set_tint and the one last uploaded, if any.
+    tint_value: std::cell::Cell<Option<[f32; 4]>>,
+    tint_uploaded: std::cell::Cell<Option<[f32; 4]>>,
     position: u32,
+    //Index buffer of triangle edges for draw_wireframe, covering `num_verts` vertices.
+    edges: std::cell::RefCell<Option<Buffer>>,
+    ctx: WebGl2RenderingContext,
+}
+
+//The three edges of each triangle as pairs of vertex indices, for drawing as lines.
+fn edge_indices(num_triangles: usize) -> Vec<u32> {
+    (0..num_triangles as u32)
+        .flat_map(|t| {
+            let a = t * 3;
+            [a, a + 1, a + 1, a + 2, a + 2, a]
+        })
+        .collect()
+}
+
+impl Drop for GlProgram {
+    fn drop(&mut self) {
+        if !self.ctx.is_context_lost() {
+            self.ctx.delete_program(Some(&self.program));
+        }
+    }
+}
+
+///
+/// Compiles a [`GlProgram`] with `#define`s inserted into both shaders, so that
+/// shaders can use `#ifdef` to compile specialized variants instead of branching at runtime.
+/// Without any defines it is the same as [`GlProgram::new`].
+///
+pub struct GlProgramBuilder<'a> {
+    vs: &'a str,
+    fs: &'a str,
+    defines: std::collections::BTreeSet<String>,
+}
+
+impl<'a> GlProgramBuilder<'a> {
+    pub fn new(vs: &'a str, fs: &'a str) -> Self {
+        GlProgramBuilder {
+            vs,
+            fs,
+            defines: std::collections::BTreeSet::new(),
+        }
+    }
+
+    pub fn define(mut self, name: &str) -> Self {
+        self.defines.insert(name.to_string());
+        self
+    }
+
+    pub fn build(&self, context: &WebGl2RenderingContext) -> Result<GlProgram, String> {
+        GlProgram::new(context, &self.insert(self.vs), &self.insert(self.fs))
+    }
+
+    //The defines have to go after the #version line.
+    fn insert(&self, source: &str) -> String {
+        let (version, rest) = match source.split_once('\n') {
+            Some((first, rest)) if first.starts_with("#version") => (first, rest),
+            _ => ("", source),
+        };
+        let mut out = String::with_capacity(source.len());
+        if !version.is_empty() {
+            out.push_str(version);
+            out.push('\n');
+        }
+        for d in self.defines.iter() {
+            out.push_str("#define ");
+            out.push_str(d);
+            out.push('\n');
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+///
+/// A cache of the variants of one pair of shaders compiled with [`GlProgramBuilder`],
+/// keyed by their set of defines. Each variant is compiled the first time it is asked for.
+///
+pub struct ProgramVariants {
+    vs: String,
+    fs: String,
+    programs: std::collections::HashMap<std::collections::BTreeSet<String>, GlProgram>,
+}
+
+impl ProgramVariants {
+    pub fn new(vs: &str, fs: &str) -> Self {
+        ProgramVariants {
+            vs: vs.to_string(),
+            fs: fs.to_string(),
+            programs: std::collections::HashMap::new(),
+        }
+    }
+
+    pub fn get(
+        &mut self,
+        context: &WebGl2RenderingContext,
+        defines: &[&str],
+    ) -> Result<&GlProgram, String> {
+        let mut builder = GlProgramBuilder::new(&self.vs, &self.fs);
+        for d in defines {
+            builder = builder.define(d);
+        }
+
+        match self.programs.entry(builder.defines.clone()) {
+            std::collections::hash_map::Entry::Occupied(e) => Ok(e.into_mut()),
+            std::collections::hash_map::Entry::Vacant(e) => Ok(e.insert(builder.build(context)?)),
+        }
+    }
+}
+
+///
+/// An active uniform or attribute of a [`GlProgram`]. See [`GlProgram::reflect`].
+///
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ActiveVariable {
+    pub name: String,
+    pub gl_type: u32,
+    pub type_name: &'static str,
+    pub size: i32,
+}
+
+///
+/// The active uniforms and attributes of a [`GlProgram`].
+///
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ProgramReflection {
+    pub uniforms: Vec<ActiveVariable>,
+    pub attributes: Vec<ActiveVariable>,
+}
+
+impl GlProgram {
+    ///
+    /// Query the active uniforms and attributes of the program.
+    ///
+    pub fn reflect(&self) -> ProgramReflection {
+        let context = &self.ctx;
+        let count = |pname| {
+            context
+                .get_program_parameter(&self.program, pname)
+                .as_f64()
+                .unwrap_or(0.0) as u32
+        };
+
+        let to_var = |info: web_sys::WebGlActiveInfo| ActiveVariable {
+            name: info.name(),
+            gl_type: info.type_(),
+            type_name: gl_type_name(info.type_()),
+            size: info.size(),
+        };
+
+        let uniforms = (0..count(WebGl2RenderingContext::ACTIVE_UNIFORMS))
+            .filter_map(|i| context.get_active_uniform(&self.program, i))
+            .map(to_var)
+            .collect();
+
+        let attributes = (0..count(WebGl2RenderingContext::ACTIVE_ATTRIBUTES))
+            .filter_map(|i| context.get_active_attrib(&self.program, i))
+            .map(to_var)
+            .collect();
+
+        ProgramReflection {
+            uniforms,
+            attributes,
+        }
+    }
+}
+
+fn gl_type_name(gl_type: u32) -> &'static str {
+    type C = WebGl2RenderingContext;
+    match gl_type {
+        C::FLOAT => "float",
+        C::FLOAT_VEC2 => "vec2",
+        C::FLOAT_VEC3 => "vec3",
+        C::FLOAT_VEC4 => "vec4",
+        C::INT => "int",
+        C::INT_VEC2 => "ivec2",
+        C::INT_VEC3 => "ivec3",
+        C::INT_VEC4 => "ivec4",
+        C::UNSIGNED_INT => "uint",
+        C::UNSIGNED_INT_VEC2 => "uvec2",
+        C::UNSIGNED_INT_VEC3 => "uvec3",
+        C::UNSIGNED_INT_VEC4 => "uvec4",
+        C::BOOL => "bool",
+        C::BOOL_VEC2 => "bvec2",
+        C::BOOL_VEC3 => "bvec3",
+        C::BOOL_VEC4 => "bvec4",
+        C::FLOAT_MAT2 => "mat2",
+        C::FLOAT_MAT3 => "mat3",
+        C::FLOAT_MAT4 => "mat4",
+        C::SAMPLER_2D => "sampler2D",
+        C::SAMPLER_3D => "sampler3D",
+        C::SAMPLER_CUBE => "samplerCube",
+        C::SAMPLER_2D_ARRAY => "sampler2DArray",
+        _ => "unknown",
+    }
+}
+
+//Unused variables are removed by the shader compiler, so a missing location
+//usually means the shader doesn't use it.
+pub(crate) fn uniform_location(
+    context: &WebGl2RenderingContext,
+    program: &WebGlProgram,
+    name: &str,
+) -> Result<WebGlUniformLocation, String> {
+    context.get_uniform_location(program, name).ok_or_else(|| {
+        format!(
+            "uniform `{}` not found, it is missing or unused in the shader",
+            name
+        )
+    })
 }
 
-fn compile_shader(
+pub(crate) fn attrib_location(
+    context: &WebGl2RenderingContext,
+    program: &WebGlProgram,
+    name: &str,
+) -> Result<u32, String> {
+    let loc = context.get_attrib_location(program, name);
+    if loc < 0 {
+        return Err(format!(
+            "attribute `{}` not found, it is missing or unused in the shader",
+            name
+        ));
+    }
+    Ok(loc as u32)
+}
+
+pub(crate) fn compile_shader(
     context: &WebGl2RenderingContext,
     shader_type: u32,
     source: &str,
@@ -128,7 +565,7 @@ fn compile_shader(
     }
 }
 
-fn link_program(
+pub(crate) fn link_program(
     context: &WebGl2RenderingContext,
     vert_shader: &WebGlShader,
     frag_shader: &WebGlShader,
@@ -153,3 +590,14 @@ fn link_program(
             .unwrap_or_else(|| String::from("Unknown error creating program object")))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edge_indices_outline_each_triangle() {
+        assert_eq!(edge_indices(0), Vec::<u32>::new());
+        assert_eq!(edge_indices(2), [0, 1, 1, 2, 2, 0, 3, 4, 4, 5, 5, 3]);
+    }
+}